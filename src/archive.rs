@@ -0,0 +1,449 @@
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::EntryType;
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// How a tarball is compressed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// The file extension of tarballs in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gzip => "tar.gz",
+            Format::Xz => "tar.xz",
+            Format::Zstd => "tar.zst",
+        }
+    }
+
+    pub fn default_level(self) -> u32 {
+        match self {
+            Format::Gzip => 6,
+            Format::Xz => 6,
+            Format::Zstd => 19,
+        }
+    }
+
+    /// The lowest and highest compression level
+    pub fn levels(self) -> (u32, u32) {
+        match self {
+            Format::Gzip => (0, 9),
+            Format::Xz => (0, 9),
+            Format::Zstd => (1, 22),
+        }
+    }
+}
+
+/// A format and how hard to compress with it
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    pub format: Format,
+    pub level: u32,
+}
+
+/// Pack everything in `source` into a tarball at `archive_path`. Symlinks are stored as
+/// symlinks, not followed.
+pub fn pack(source: &Path, archive_path: &Path, compression: Compression) -> Result<()> {
+    let (min_level, max_level) = compression.format.levels();
+    if compression.level < min_level || compression.level > max_level {
+        bail!(
+            "{:?} compression levels go from {} to {}, not {}",
+            compression.format,
+            min_level,
+            max_level,
+            compression.level
+        );
+    }
+
+    let file = fs::File::create(archive_path)
+        .with_context(|| format!("Creating {}", archive_path.display()))?;
+    let writer = BufWriter::new(file);
+    let level = compression.level;
+
+    match compression.format {
+        Format::Gzip => {
+            let encoder = GzEncoder::new(writer, flate2::Compression::new(level));
+            append_dir(encoder, source)?.finish()?.flush()?;
+        }
+        Format::Xz => {
+            let encoder = xz2::write::XzEncoder::new(writer, level);
+            append_dir(encoder, source)?.finish()?.flush()?;
+        }
+        Format::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, level as i32)?;
+            // The high levels are slow, so use every core for large prefixes
+            let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            encoder.multithread(threads as u32)?;
+            append_dir(encoder, source)?.finish()?.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a tarball of everything in `source` to `writer`, and hand the writer back so
+/// its compression can be finished
+fn append_dir<W: Write>(writer: W, source: &Path) -> Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", source)
+        .with_context(|| format!("Packing {}", source.display()))?;
+
+    Ok(builder.into_inner()?)
+}
+
+/// Unpack the tarball at `archive_path` into `destination`, which is created if needed.
+/// Gzip, xz and zstd compression are detected from the file itself. `progress` is called
+/// with the path of every entry before it's unpacked.
+///
+/// Archives come from the internet, so any entry that would end up outside of
+/// `destination` is an error: absolute paths, `..` and links that point outside.
+pub fn unpack(
+    archive_path: &Path,
+    destination: &Path,
+    progress: impl FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    unpack_archive(archive_path, destination, None, false, progress)
+}
+
+/// Unpack a wineprefix that boxwine packed into `destination`. Entries still can't be
+/// written outside of it, but links can point anywhere: wineprefixes link outside
+/// themselves on purpose, like `dosdevices/z:` to `/`.
+pub fn unpack_wineprefix(
+    archive_path: &Path,
+    destination: &Path,
+    format: Option<Format>,
+) -> Result<()> {
+    unpack_archive(archive_path, destination, format, true, |_| Ok(()))
+}
+
+/// Unpack with the given decoder, or the detected one without a `format`
+fn unpack_archive(
+    archive_path: &Path,
+    destination: &Path,
+    format: Option<Format>,
+    external_links: bool,
+    mut progress: impl FnMut(&Path) -> Result<()>,
+) -> Result<()> {
+    let file = fs::File::open(archive_path)
+        .with_context(|| format!("Opening {}", archive_path.display()))?;
+    let reader = decoder(BufReader::new(file), format)
+        .with_context(|| format!("Reading {}", archive_path.display()))?;
+
+    fs::create_dir_all(destination)
+        .with_context(|| format!("Creating {}", destination.display()))?;
+    let root = destination
+        .canonicalize()
+        .with_context(|| format!("Finding {}", destination.display()))?;
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Reading {}", archive_path.display()))?;
+    for entry in entries {
+        let mut entry = entry.with_context(|| format!("Reading {}", archive_path.display()))?;
+        let path = entry.path()?.into_owned();
+
+        check_entry(&entry, &path, &root, external_links)
+            .with_context(|| format!("Refusing to unpack {}", path.display()))?;
+        progress(&path)?;
+
+        // unpack_in skips entries it finds unsafe, those were all caught above
+        let unpacked = entry
+            .unpack_in(destination)
+            .with_context(|| format!("Unpacking {}", path.display()))?;
+        if !unpacked {
+            bail!("Refusing to unpack {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Wrap `reader` in the decoder for `format`, or for whatever compression its first
+/// bytes show
+fn decoder<'a>(
+    mut reader: impl BufRead + 'a,
+    format: Option<Format>,
+) -> Result<Box<dyn Read + 'a>> {
+    let format = match format {
+        Some(format) => Some(format),
+        None => detect_format(reader.fill_buf()?),
+    };
+
+    Ok(match format {
+        Some(Format::Gzip) => Box::new(GzDecoder::new(reader)),
+        Some(Format::Xz) => Box::new(xz2::read::XzDecoder::new(reader)),
+        Some(Format::Zstd) => Box::new(zstd::Decoder::with_buffer(reader)?),
+        None => Box::new(reader),
+    })
+}
+
+fn detect_format(magic: &[u8]) -> Option<Format> {
+    if magic.starts_with(GZIP_MAGIC) {
+        Some(Format::Gzip)
+    } else if magic.starts_with(XZ_MAGIC) {
+        Some(Format::Xz)
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Some(Format::Zstd)
+    } else {
+        None
+    }
+}
+
+/// Make sure an entry, and unless `external_links` whatever it links to, stays inside
+/// `root`, the canonical destination
+fn check_entry<R: Read>(
+    entry: &tar::Entry<R>,
+    path: &Path,
+    root: &Path,
+    external_links: bool,
+) -> Result<()> {
+    let entry_type = entry.header().entry_type();
+    let depth = relative_depth(0, path)
+        .ok_or_else(|| anyhow!("Its path leaves the destination directory"))?;
+    if depth == 0 && entry_type != EntryType::Directory {
+        bail!("It has an empty path");
+    }
+
+    if !external_links && (entry_type.is_symlink() || entry_type.is_hard_link()) {
+        let target = entry
+            .link_name()?
+            .ok_or_else(|| anyhow!("It's a link without a target"))?;
+
+        // Symlinks are relative to the directory they're in, hard links to the archive.
+        // Links that were unpacked before can put that directory anywhere, so it's
+        // followed on disk rather than going by its path.
+        let base = match (entry_type.is_symlink(), path.parent()) {
+            (true, Some(parent)) => resolve_inside(root, root, parent).ok_or_else(|| {
+                anyhow!("It's in {}, which links outside the destination", parent.display())
+            })?,
+            _ => root.to_path_buf(),
+        };
+        if resolve_inside(root, &base, &target).is_none() {
+            bail!("It links to {}, outside the destination", target.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Where `path` ends up when it's followed from `base` through whatever is in `root`
+/// already, or nothing when it leaves `root`. Both have to be canonical. Going up out of
+/// a directory that doesn't exist yet counts as leaving, because it could still be
+/// unpacked as a link.
+fn resolve_inside(root: &Path, base: &Path, path: &Path) -> Option<PathBuf> {
+    let mut resolved = base.to_path_buf();
+    let mut exists = true;
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name);
+                if exists {
+                    match resolved.canonicalize() {
+                        Ok(canonical) => resolved = canonical,
+                        Err(_) => exists = false,
+                    }
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir if exists => {
+                resolved.pop();
+            }
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+
+        if !resolved.starts_with(root) {
+            return None;
+        }
+    }
+
+    Some(resolved)
+}
+
+/// How many directories deep `path` ends up when starting `depth` directories deep in
+/// the destination, or nothing when it's absolute or goes above the destination
+fn relative_depth(mut depth: usize, path: &Path) -> Option<usize> {
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// An entry of a test tarball. Paths and link targets are written into the header
+    /// as they are, because the tar crate won't build archives with unsafe ones.
+    struct Entry<'a> {
+        path: &'a str,
+        entry_type: EntryType,
+        link: &'a str,
+    }
+
+    fn file(path: &str) -> Entry<'_> {
+        Entry {
+            path,
+            entry_type: EntryType::Regular,
+            link: "",
+        }
+    }
+
+    fn directory(path: &str) -> Entry<'_> {
+        Entry {
+            path,
+            entry_type: EntryType::Directory,
+            link: "",
+        }
+    }
+
+    fn symlink<'a>(path: &'a str, target: &'a str) -> Entry<'a> {
+        Entry {
+            path,
+            entry_type: EntryType::Symlink,
+            link: target,
+        }
+    }
+
+    fn hard_link<'a>(path: &'a str, target: &'a str) -> Entry<'a> {
+        Entry {
+            path,
+            entry_type: EntryType::Link,
+            link: target,
+        }
+    }
+
+    /// A directory with an uncompressed tarball of `entries` and an empty destination in
+    /// it, with a file outside of the destination that nothing may reach
+    fn unpack_entries(entries: &[Entry]) -> (TempDir, Result<()>) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("outside"), "outside").unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for entry in entries {
+            // Files hold their own path, so tests can tell which one they reached
+            let contents = match entry.entry_type {
+                EntryType::Regular => entry.path.as_bytes(),
+                _ => &[],
+            };
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..entry.path.len()].copy_from_slice(entry.path.as_bytes());
+            header.as_old_mut().linkname[..entry.link.len()]
+                .copy_from_slice(entry.link.as_bytes());
+            header.set_entry_type(entry.entry_type);
+            header.set_mode(0o755);
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        }
+        let archive_path = dir.path().join("archive.tar");
+        fs::write(&archive_path, builder.into_inner().unwrap()).unwrap();
+
+        let result = unpack(&archive_path, &dir.path().join("destination"), |_| Ok(()));
+        (dir, result)
+    }
+
+    fn assert_refused(entries: &[Entry]) -> TempDir {
+        let (dir, result) = unpack_entries(entries);
+        let error = format!("{:#}", result.expect_err("The archive was unpacked"));
+        assert!(error.contains("Refusing to unpack"), "{}", error);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("outside")).unwrap(),
+            "outside"
+        );
+
+        dir
+    }
+
+    #[test]
+    fn unpacks_entries_and_links_inside() {
+        let (dir, result) = unpack_entries(&[
+            directory("bin/"),
+            file("bin/wine"),
+            symlink("bin/wine64", "wine"),
+            symlink("lib", "bin/../bin"),
+            symlink("lib/loader", "../bin/wine"),
+            hard_link("wine", "bin/wine"),
+        ]);
+        result.unwrap();
+
+        let destination = dir.path().join("destination");
+        for path in &["bin/wine", "bin/wine64", "bin/loader", "wine"] {
+            assert_eq!(
+                fs::read_to_string(destination.join(path)).unwrap(),
+                "bin/wine"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_absolute_paths() {
+        let dir = assert_refused(&[file("/tmp/boxwine-absolute")]);
+        assert!(!dir.path().join("tmp").exists());
+    }
+
+    #[test]
+    fn refuses_parent_directories() {
+        assert_refused(&[file("../outside")]);
+        assert_refused(&[directory("bin/"), file("bin/../../outside")]);
+    }
+
+    #[test]
+    fn refuses_symlinks_outside() {
+        let dir = assert_refused(&[symlink("link", "../outside")]);
+        assert!(fs::symlink_metadata(dir.path().join("destination/link")).is_err());
+
+        assert_refused(&[symlink("link", "/etc")]);
+        assert_refused(&[directory("bin/"), symlink("bin/link", "../../outside")]);
+    }
+
+    #[test]
+    fn refuses_hard_links_outside() {
+        let dir = assert_refused(&[hard_link("link", "../outside")]);
+        assert!(fs::symlink_metadata(dir.path().join("destination/link")).is_err());
+
+        assert_refused(&[hard_link("link", "/etc/passwd")]);
+    }
+
+    #[test]
+    fn refuses_symlink_chains_outside() {
+        // a/b looks like it's a directory deep, but a is the destination itself
+        let dir = assert_refused(&[
+            symlink("a", "."),
+            symlink("a/b", ".."),
+            file("a/b/outside"),
+        ]);
+        assert!(fs::symlink_metadata(dir.path().join("destination/b")).is_err());
+
+        // c only points inside while q isn't a link to the destination
+        let dir = assert_refused(&[symlink("c", "q/../x"), symlink("q", ".")]);
+        assert!(fs::symlink_metadata(dir.path().join("destination/c")).is_err());
+    }
+}
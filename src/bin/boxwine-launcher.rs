@@ -0,0 +1,32 @@
+//! The executable that gets copied into `Contents/MacOS` of every app boxwine creates.
+//! It reads the bundle manifest, gets the wineprefix ready and hands over to wine.
+
+use anyhow::{Context, Result};
+use boxwine::launcher::Launcher;
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+fn main() -> Result<()> {
+    let executable = env::current_exe().with_context(|| "Locating the launcher executable")?;
+    let launcher = Launcher::from_executable(&executable)?;
+
+    launcher.prepare_wineprefix()?;
+    launcher.link_persisted_paths()?;
+
+    if !launcher.persists_registry() {
+        // exec only returns if wine couldn't be started
+        let error = launcher.command().exec();
+        return Err(error).with_context(|| format!("Starting {}", launcher.wine_path().display()));
+    }
+
+    // Registry keys can only be saved once the app quits, so wine has to be waited for
+    launcher.import_registry()?;
+    let status = launcher
+        .command()
+        .status()
+        .with_context(|| format!("Starting {}", launcher.wine_path().display()))?;
+    launcher.export_registry()?;
+
+    process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,420 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE_NAME: &str = "index.toml";
+const LOCK_FILE_NAME: &str = "index.lock";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Manage the cache of downloaded wine builds
+#[derive(Parser)]
+pub struct Cache {
+    #[clap(subcommand)]
+    subcmd: CacheCommand,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List everything in the cache
+    List,
+
+    /// Remove downloads that haven't been used in a while
+    Prune {
+        /// Remove downloads that haven't been used for this many days
+        #[clap(long, default_value = "30")]
+        older_than: u64,
+
+        /// Remove everything
+        #[clap(long)]
+        all: bool,
+    },
+
+    /// Add a file that was downloaded some other way, for example for offline builds
+    Import {
+        /// The file to add
+        file: String,
+
+        /// URL the file was downloaded from. Without it, the file is used for any
+        /// download with the same file name.
+        #[clap(long)]
+        url: Option<String>,
+    },
+}
+
+pub fn cache(opts: Cache) -> Result<()> {
+    let mut cache = DownloadCache::open()?;
+
+    match opts.subcmd {
+        CacheCommand::List => cache.list(),
+        CacheCommand::Prune { older_than, all } => cache.prune(if all { 0 } else { older_than }),
+        CacheCommand::Import { file, url } => {
+            let file_path = Path::new(&file);
+            let file_name = file_name(file_path)?;
+            let blob_path = cache.insert(url.as_deref(), &file_name, file_path, false)?;
+            println!("Imported {} as {}", file, blob_path.display());
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Where the file was downloaded from, empty for imported files
+    url: Option<String>,
+    file_name: String,
+    sha256: String,
+    size: u64,
+
+    /// Seconds since the epoch of the last build that used this file
+    last_used: u64,
+}
+
+/// Downloads shared between every build on this machine, stored by their SHA-256 in
+/// `$XDG_CACHE_HOME/boxwine` and looked up by the URL they came from.
+///
+/// Builds can run at the same time, so every change to the index is made to the index
+/// on disk while holding a lock on it.
+pub struct DownloadCache {
+    dir: PathBuf,
+    index: Index,
+}
+
+impl DownloadCache {
+    pub fn open() -> Result<DownloadCache> {
+        DownloadCache::open_in(cache_dir()?)
+    }
+
+    /// The cache in `dir` instead of the usual cache directory
+    pub fn open_in(dir: PathBuf) -> Result<DownloadCache> {
+        fs::create_dir_all(dir.join("blobs"))
+            .with_context(|| format!("Creating cache directory {}", dir.display()))?;
+        let index = read_index(&dir)?;
+
+        Ok(DownloadCache { dir, index })
+    }
+
+    /// Where a download of `url` goes until it's complete, on the same file system as
+    /// the cache. It's named after the whole URL, so downloads of different files with
+    /// the same name never resume each other.
+    pub fn partial_download_path(&self, url: &str) -> Result<PathBuf> {
+        let download_dir = self.dir.join("downloads");
+        fs::create_dir_all(&download_dir)
+            .with_context(|| format!("Creating {}", download_dir.display()))?;
+
+        Ok(download_dir.join(format!("{}.part", sha256_hex(Sha256::digest(url)))))
+    }
+
+    /// Path of the cached download of `url`, if there is one. With a `sha256`, any file
+    /// with that hash will do, whatever URL it came from. Files imported without a URL
+    /// are found by their file name.
+    pub fn lookup(&mut self, url: &str, sha256: Option<&str>) -> Result<Option<PathBuf>> {
+        let file_name = url_file_name(url)?;
+        let blobs_dir = self.dir.join("blobs");
+
+        self.update(|index| {
+            let entries = &index.entries;
+            let position = match sha256 {
+                Some(sha256) => entries
+                    .iter()
+                    .position(|entry| entry.sha256.eq_ignore_ascii_case(sha256)),
+                None => entries
+                    .iter()
+                    .position(|entry| entry.url.as_deref() == Some(url))
+                    .or_else(|| {
+                        entries
+                            .iter()
+                            .position(|entry| entry.url.is_none() && entry.file_name == file_name)
+                    }),
+            };
+
+            let entry = match position {
+                Some(position) => &mut index.entries[position],
+                None => return Ok(None),
+            };
+
+            let blob_path = blobs_dir.join(&entry.sha256);
+            if !blob_path.exists() {
+                return Ok(None);
+            }
+
+            entry.last_used = now();
+
+            Ok(Some(blob_path))
+        })
+    }
+
+    /// Add `file_path` to the cache and return where it's stored. With `take`, the file is
+    /// moved into the cache instead of copied.
+    pub fn insert(
+        &mut self,
+        url: Option<&str>,
+        file_name: &str,
+        file_path: &Path,
+        take: bool,
+    ) -> Result<PathBuf> {
+        let sha256 = sha256_file(file_path)?;
+        let size = fs::metadata(file_path)?.len();
+        let blob_path = self.dir.join("blobs").join(&sha256);
+
+        // The file goes in under the lock too, so a prune never sees it without its entry
+        self.update(|index| {
+            if take {
+                fs::rename(file_path, &blob_path)
+            } else {
+                fs::copy(file_path, &blob_path).map(|_| ())
+            }
+            .with_context(|| format!("Adding {} to the cache", file_path.display()))?;
+
+            index
+                .entries
+                .retain(|entry| !(entry.url.as_deref() == url && entry.file_name == file_name));
+            index.entries.push(Entry {
+                url: url.map(str::to_string),
+                file_name: file_name.to_string(),
+                sha256,
+                size,
+                last_used: now(),
+            });
+
+            Ok(blob_path)
+        })
+    }
+
+    fn list(&self) -> Result<()> {
+        if self.index.entries.is_empty() {
+            println!("The cache in {} is empty", self.dir.display());
+        }
+
+        for entry in &self.index.entries {
+            let days_unused = now().saturating_sub(entry.last_used) / SECONDS_PER_DAY;
+            println!(
+                "{}  {:>6} MiB  used {} days ago  {}",
+                &entry.sha256[..12],
+                entry.size / (1024 * 1024),
+                days_unused,
+                entry.url.as_deref().unwrap_or(&entry.file_name)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Remove entries that weren't used for `older_than` days, any files no entry refers
+    /// to anymore, and partial downloads that haven't been written to in that time
+    fn prune(&mut self, older_than: u64) -> Result<()> {
+        let cutoff = now().saturating_sub(older_than * SECONDS_PER_DAY);
+        let dir = self.dir.clone();
+
+        let (removed, freed) = self.update(|index| {
+            let entry_count = index.entries.len();
+            index
+                .entries
+                .retain(|entry| older_than > 0 && entry.last_used >= cutoff);
+
+            let mut freed = 0;
+            let blobs_dir = dir.join("blobs");
+            for blob in fs::read_dir(&blobs_dir)? {
+                let blob = blob?;
+                let sha256 = blob.file_name().to_string_lossy().to_string();
+
+                if !index.entries.iter().any(|entry| entry.sha256 == sha256) {
+                    freed += blob.metadata()?.len();
+                    fs::remove_file(blob.path())
+                        .with_context(|| format!("Removing {}", blob.path().display()))?;
+                }
+            }
+
+            Ok((entry_count - index.entries.len(), freed))
+        })?;
+
+        // A partial download that was written to since could be one that's going on
+        let download_dir = self.dir.join("downloads");
+        let mut partials_removed = 0;
+        if download_dir.exists() {
+            for download in fs::read_dir(&download_dir)? {
+                let download = download?;
+                let modified = download.metadata()?.modified()?;
+                if seconds_since_epoch(modified) < cutoff {
+                    fs::remove_file(download.path())
+                        .with_context(|| format!("Removing {}", download.path().display()))?;
+                    partials_removed += 1;
+                }
+            }
+        }
+
+        println!(
+            "Removed {} entries and {} partial downloads, freed {} MiB",
+            removed,
+            partials_removed,
+            freed / (1024 * 1024)
+        );
+
+        Ok(())
+    }
+
+    /// Change the index on disk while holding the lock on it, and keep the result
+    fn update<T>(&mut self, change: impl FnOnce(&mut Index) -> Result<T>) -> Result<T> {
+        let lock_path = self.dir.join(LOCK_FILE_NAME);
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Opening {}", lock_path.display()))?;
+        lock.lock()
+            .with_context(|| format!("Locking {}", lock_path.display()))?;
+
+        // Whatever builds running at the same time changed since this one read it
+        self.index = read_index(&self.dir)?;
+        let result = change(&mut self.index)?;
+        self.save()?;
+
+        Ok(result)
+    }
+
+    fn save(&self) -> Result<()> {
+        let index_path = self.dir.join(INDEX_FILE_NAME);
+        let temp_path = index_path.with_extension("toml.tmp");
+        let contents = toml::to_string(&self.index).with_context(|| "Serializing cache index")?;
+
+        // Write the new index next to the old one and swap them, so a build running at
+        // the same time never reads half an index
+        fs::write(&temp_path, contents)
+            .with_context(|| format!("Writing {}", temp_path.display()))?;
+        fs::rename(&temp_path, &index_path)
+            .with_context(|| format!("Writing {}", index_path.display()))
+    }
+}
+
+fn read_index(dir: &Path) -> Result<Index> {
+    let index_path = dir.join(INDEX_FILE_NAME);
+    if !index_path.exists() {
+        return Ok(Index::default());
+    }
+
+    let contents = fs::read_to_string(&index_path)
+        .with_context(|| format!("Reading {}", index_path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("Parsing {}", index_path.display()))
+}
+
+/// `$XDG_CACHE_HOME/boxwine`, falling back to `~/.cache/boxwine`
+fn cache_dir() -> Result<PathBuf> {
+    if let Some(cache_home) = env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(cache_home).join("boxwine"));
+    }
+
+    let home = env::var_os("HOME")
+        .ok_or_else(|| anyhow!("Can't find the cache directory, $HOME isn't set"))?;
+    Ok(PathBuf::from(home).join(".cache/boxwine"))
+}
+
+/// File name of whatever is downloaded from `url`
+pub fn url_file_name(url: &str) -> Result<String> {
+    match url.rsplit('/').next() {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => bail!("Can't tell the name of the file to download from {}", url),
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Hashing {}", path.display()))?;
+
+    Ok(sha256_hex(hasher.finalize()))
+}
+
+fn sha256_hex(hash: impl AsRef<[u8]>) -> String {
+    hash.as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now() -> u64 {
+    seconds_since_epoch(SystemTime::now())
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn partial_downloads_of_different_urls_are_apart() {
+        let dir = TempDir::new().unwrap();
+        let cache = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+
+        let first = cache.partial_download_path("https://a.example/wine.tar.gz");
+        let second = cache.partial_download_path("https://b.example/wine.tar.gz");
+        assert_ne!(first.unwrap(), second.unwrap());
+    }
+
+    #[test]
+    fn builds_at_the_same_time_keep_each_others_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut first = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+        let mut second = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+
+        for (cache, name) in [(&mut first, "a.tar.gz"), (&mut second, "b.tar.gz")] {
+            let file = dir.path().join(name);
+            fs::write(&file, name).unwrap();
+            let url = format!("https://example.com/{}", name);
+            cache.insert(Some(&url), name, &file, true).unwrap();
+        }
+
+        let mut cache = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+        for name in ["a.tar.gz", "b.tar.gz"] {
+            let url = format!("https://example.com/{}", name);
+            assert!(cache.lookup(&url, None).unwrap().is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn prune_keeps_recent_partial_downloads() {
+        let dir = TempDir::new().unwrap();
+        let mut cache = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+
+        let recent = cache.partial_download_path("https://example.com/recent").unwrap();
+        let stale = cache.partial_download_path("https://example.com/stale").unwrap();
+        fs::write(&recent, "recent").unwrap();
+        fs::write(&stale, "stale").unwrap();
+        let long_ago = SystemTime::now() - std::time::Duration::from_secs(40 * SECONDS_PER_DAY);
+        fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+
+        cache.prune(30).unwrap();
+
+        assert!(recent.exists());
+        assert!(!stale.exists());
+    }
+}
+
@@ -1,16 +1,74 @@
+use crate::resolve::{self, Origin, Resolved, SourceFile};
+use crate::validate::{self, Diagnostic};
+use crate::variables;
+
+use anyhow::{Context, Result};
+use boxwine::archive::{Compression, Format};
+use boxwine::interpolate::{DRIVE_C_VARIABLE, WINEPREFIX_VARIABLE};
+use boxwine::launcher::persisted_path;
+use boxwine::manifest::{Persist, UpgradePolicy};
+use boxwine::winpath::WindowsPath;
 use serde::Deserialize;
-use std::ffi::OsStr;
-use std::fs;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Where every user's wineprefix goes unless the config says otherwise, see
+/// `Manifest::wineprefix_location`
+const DEFAULT_WINEPREFIX_LOCATION: &str = "{data_dir}/{identifier}/prefix";
+
+/// What `delete_installers` removes: the copies of installers windows keeps around for
+/// repairs and uninstalls
+const INSTALLERS_PATTERN: &str = "c:/windows/Installer/*";
 
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 struct App {
     name: String,
     icon: Option<String>,
+    identifier: Option<String>,
+    display_name: Option<String>,
+    version: Option<String>,
+    build: Option<String>,
+    minimum_system_version: Option<String>,
+    high_resolution: Option<bool>,
+    category: Option<String>,
+    plist_format: PlistFormat,
+
     entrypoint: Run,
+    persist: Option<Vec<Persist>>,
 }
 
-#[derive(Deserialize)]
+impl Default for App {
+    fn default() -> App {
+        App {
+            name: "My App".to_string(),
+            icon: None,
+            identifier: None,
+            display_name: None,
+            version: None,
+            build: None,
+            minimum_system_version: None,
+            high_resolution: None,
+            category: None,
+            plist_format: PlistFormat::Xml,
+            entrypoint: Run::default(),
+            persist: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PlistFormat {
+    #[default]
+    Xml,
+    Binary,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
 struct Wine {
+    source: Option<WineSource>,
     build: Build,
     prefix: Prefix,
 
@@ -21,94 +79,213 @@ struct Wine {
     runs: Vec<Run>,
 }
 
+/// Where the wine that goes into the app comes from
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum WineSource {
+    /// Portable builds from dl.winehq.org
+    Winehq,
+
+    /// Builds from Gcenx's macOS_Wine_builds, or the CrossOver-derived winecx builds
+    /// with branch "crossover"
+    Gcenx,
+
+    /// A tarball at a URL, where {branch}, {version} and {arch} are filled in from
+    /// [wine.build]
+    Url { url: String },
+
+    /// A tarball on the host
+    Tarball { path: String },
+
+    /// Wine that's already unpacked on the host
+    Directory { path: String },
+}
+
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 struct Build {
     branch: String,
     version: String,
     arch: String,
+    sha256: Option<String>,
+}
+
+impl Default for Build {
+    fn default() -> Build {
+        Build {
+            branch: "stable".to_string(),
+            version: "5.0".to_string(),
+            arch: "64".to_string(),
+            sha256: None,
+        }
+    }
 }
 
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 struct Prefix {
-    prefix_arch: String,
+    prefix_arch: PrefixArch,
     base_prefix: Option<String>,
+    location: Option<String>,
+    upgrade: Option<UpgradePolicy>,
     sandbox: bool,
     install_gecko: bool,
     install_mono: bool,
     delete_installers: bool,
-    compress_wineprefix: bool,
+    prune: Option<Vec<String>>,
+    compress_wineprefix: CompressWineprefix,
+}
+
+impl Default for Prefix {
+    fn default() -> Prefix {
+        Prefix {
+            prefix_arch: PrefixArch::Win64,
+            base_prefix: None,
+            location: None,
+            upgrade: None,
+            sandbox: true,
+            install_gecko: false,
+            install_mono: false,
+            delete_installers: true,
+            prune: None,
+            compress_wineprefix: CompressWineprefix::Switch(true),
+        }
+    }
+}
+
+/// The Windows architecture of the wineprefix, which wine gets as WINEARCH
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrefixArch {
+    Win32,
+    Win64,
+    Wow64,
 }
 
+impl PrefixArch {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PrefixArch::Win32 => "win32",
+            PrefixArch::Win64 => "win64",
+            PrefixArch::Wow64 => "wow64",
+        }
+    }
+}
+
+/// How to compress the wineprefix: an on/off switch that means gzip when on, a format,
+/// or a format with a level
 #[derive(Deserialize)]
+#[serde(untagged)]
+enum CompressWineprefix {
+    Switch(bool),
+    Format(CompressionFormat),
+    WithLevel(CompressionWithLevel),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompressionWithLevel {
+    format: CompressionFormat,
+    level: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CompressionFormat {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Volume {
     pub from: String,
     pub to: String,
     pub post_install: Option<bool>,
+    pub exclude: Option<Vec<String>>,
+    pub mode: Option<VolumeMode>,
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
 }
 
-#[derive(Deserialize)]
+/// How a volume's files get into the wineprefix
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeMode {
+    #[default]
+    Copy,
+    Symlink,
+    Hardlink,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Run {
     pub program: String,
     pub args: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub cwd: Option<String>,
+    pub timeout: Option<u64>,
+    pub success_codes: Option<Vec<i32>>,
+    pub wait: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
 struct Winetricks {
     verbs: Vec<String>,
 }
 
-#[derive(Deserialize)]
-#[serde(default)]
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     app: App,
     wine: Wine,
     winetricks: Winetricks,
-}
 
-impl Default for Config {
-    fn default() -> Config {
-        Config {
-            app: App {
-                name: "My App".to_string(),
-                icon: None,
-                entrypoint: Run {
-                    program: "".to_string(),
-                    args: None,
-                },
-            },
-            wine: Wine {
-                build: Build {
-                    branch: "stable".to_string(),
-                    version: "5.0".to_string(),
-                    arch: "64".to_string(),
-                },
-                prefix: Prefix {
-                    prefix_arch: "win64".to_string(),
-                    base_prefix: None,
-                    sandbox: true,
-                    install_mono: true,
-                    install_gecko: false,
-                    delete_installers: true,
-                    compress_wineprefix: true,
-                },
-                volumes: vec![],
-                runs: vec![],
-            },
-            winetricks: Winetricks { verbs: vec![] },
-        }
-    }
+    /// What the profile the app is built with adds to its bundle identifier
+    #[serde(skip)]
+    suffix: Option<String>,
 }
 
 impl Config {
-    pub fn get_portable_wine_url(&self) -> String {
+    pub fn get_wine_source(&self) -> WineSource {
+        self.wine.source.clone().unwrap_or(WineSource::Winehq)
+    }
+
+    /// The URL to download wine from, for sources that download it
+    pub fn get_wine_url(&self) -> Option<String> {
         let branch = &self.wine.build.branch;
         let version = &self.wine.build.version;
         let arch = &self.wine.build.arch;
 
-        return format!(
-            "https://dl.winehq.org/wine-builds/macosx/pool/portable-winehq-{}-{}-osx{}.tar.gz",
-            branch, version, arch
-        );
+        match self.get_wine_source() {
+            WineSource::Winehq => Some(format!(
+                "https://dl.winehq.org/wine-builds/macosx/pool/portable-winehq-{}-{}-osx{}.tar.gz",
+                branch, version, arch
+            )),
+            WineSource::Gcenx if branch == "crossover" => Some(format!(
+                "https://github.com/Gcenx/winecx/releases/download/crossover-wine-{}/wine-crossover-{}-osx64.tar.xz",
+                version, version
+            )),
+            WineSource::Gcenx => Some(format!(
+                "https://github.com/Gcenx/macOS_Wine_builds/releases/download/{}/wine-{}-{}-osx64.tar.xz",
+                version, branch, version
+            )),
+            WineSource::Url { url } => Some(
+                url.replace("{branch}", branch)
+                    .replace("{version}", version)
+                    .replace("{arch}", arch),
+            ),
+            WineSource::Tarball { .. } | WineSource::Directory { .. } => None,
+        }
+    }
+
+    /// The SHA-256 the wine tarball must have, if it was pinned
+    pub fn get_wine_sha256(&self) -> Option<&str> {
+        self.wine.build.sha256.as_deref()
     }
 
     // WINEDLLOVERRIDES will let us ignore gecko and mono
@@ -122,39 +299,398 @@ impl Config {
         }
         let winedlloverrides = format!("{}=", overrides.join(","));
 
-        return winedlloverrides;
+        winedlloverrides
+    }
+
+    pub fn get_app_name(&self) -> &String {
+        &self.app.name
+    }
+
+    /// The bundle identifier, made up from the app name if none was configured
+    fn get_base_bundle_identifier(&self) -> String {
+        match &self.app.identifier {
+            Some(identifier) => identifier.clone(),
+            None => {
+                let name = self
+                    .app
+                    .name
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<&str>>()
+                    .join("-");
+                format!("com.boxwine.{}", name)
+            }
+        }
+    }
+
+    /// The bundle identifier, with the suffix of the profile so the app can be installed
+    /// next to the ones built with other profiles
+    pub fn get_bundle_identifier(&self) -> String {
+        match &self.suffix {
+            Some(suffix) => format!("{}.{}", self.get_base_bundle_identifier(), suffix),
+            None => self.get_base_bundle_identifier(),
+        }
+    }
+
+    pub fn get_display_name(&self) -> &String {
+        self.app.display_name.as_ref().unwrap_or(&self.app.name)
+    }
+
+    pub fn get_version(&self) -> &str {
+        self.app.version.as_deref().unwrap_or("1.0")
+    }
+
+    pub fn get_build_version(&self) -> &str {
+        self.app
+            .build
+            .as_deref()
+            .unwrap_or_else(|| self.get_version())
+    }
+
+    pub fn get_minimum_system_version(&self) -> &str {
+        self.app
+            .minimum_system_version
+            .as_deref()
+            .unwrap_or("10.13")
+    }
+
+    pub fn get_high_resolution(&self) -> bool {
+        self.app.high_resolution.unwrap_or(true)
+    }
+
+    pub fn get_app_category(&self) -> &Option<String> {
+        &self.app.category
+    }
+
+    pub fn get_plist_format(&self) -> PlistFormat {
+        self.app.plist_format
+    }
+
+    pub fn get_entrypoint(&self) -> &Run {
+        &self.app.entrypoint
+    }
+
+    /// User data the launcher keeps outside of the wineprefix
+    pub fn get_persist(&self) -> Vec<Persist> {
+        self.app.persist.clone().unwrap_or_default()
     }
 
     pub fn get_app_icon(&self) -> &Option<String> {
-        return &self.app.icon;
+        &self.app.icon
     }
 
     pub fn get_verbs(&self) -> &Vec<String> {
-        return &self.winetricks.verbs;
+        &self.winetricks.verbs
     }
 
     pub fn get_sandbox(&self) -> &bool {
-        return &self.wine.prefix.sandbox;
+        &self.wine.prefix.sandbox
+    }
+
+    pub fn get_prefix_arch(&self) -> PrefixArch {
+        self.wine.prefix.prefix_arch
     }
 
     pub fn get_base_prefix(&self) -> &Option<String> {
-        return &self.wine.prefix.base_prefix;
+        &self.wine.prefix.base_prefix
     }
 
-    pub fn get_compress_wineprefix(&self) -> &bool {
-        return &self.wine.prefix.compress_wineprefix;
+    /// Patterns of what to remove from the wineprefix once everything is installed
+    pub fn get_prune_patterns(&self) -> Vec<String> {
+        let mut patterns = Vec::new();
+        if self.wine.prefix.delete_installers {
+            patterns.push(INSTALLERS_PATTERN.to_string());
+        }
+        patterns.extend(self.wine.prefix.prune.iter().flatten().cloned());
+
+        patterns
+    }
+
+    /// Where the launcher puts every user's copy of the wineprefix, nothing meaning it's
+    /// used right in the bundle
+    pub fn get_wineprefix_location(&self) -> Option<String> {
+        match self.wine.prefix.location.as_deref() {
+            Some("bundle") => None,
+            Some(location) => Some(location.to_string()),
+            None => Some(DEFAULT_WINEPREFIX_LOCATION.to_string()),
+        }
+    }
+
+    /// What the launcher does with a user's wineprefix from an older build of the app
+    pub fn get_wineprefix_upgrade(&self) -> UpgradePolicy {
+        self.wine.prefix.upgrade.unwrap_or_default()
+    }
+
+    /// How to compress the wineprefix in the app, if at all
+    pub fn get_wineprefix_compression(&self) -> Option<Compression> {
+        let (format, level) = match &self.wine.prefix.compress_wineprefix {
+            CompressWineprefix::Switch(false) => return None,
+            CompressWineprefix::Switch(true) => (Format::Gzip, None),
+            CompressWineprefix::Format(format) => (to_archive_format(*format)?, None),
+            CompressWineprefix::WithLevel(CompressionWithLevel { format, level }) => {
+                (to_archive_format(*format)?, *level)
+            }
+        };
+
+        Some(Compression {
+            format,
+            level: level.unwrap_or_else(|| format.default_level()),
+        })
     }
 
     pub fn get_volumes(&self) -> &Vec<Volume> {
-        return &self.wine.volumes;
+        &self.wine.volumes
     }
 
     pub fn get_runs(&self) -> &Vec<Run> {
-        return &self.wine.runs;
+        &self.wine.runs
+    }
+}
+
+/// Read the config file at `path` together with the files it extends and includes and
+/// the overrides of `profile`, see `from_resolved`
+pub fn load(path: &str, profile: Option<&str>, wineprefix_path: Option<&Path>) -> Result<Config> {
+    let mut resolved = resolve::resolve(path)?;
+    resolved.select_profile(profile)?;
+
+    from_resolved(resolved, wineprefix_path)
+}
+
+/// Fill in the variables of a resolved config and make sure it makes sense. The
+/// wineprefix variables are only filled in with a `wineprefix_path`.
+pub fn from_resolved(mut resolved: Resolved, wineprefix_path: Option<&Path>) -> Result<Config> {
+    let path = resolved.path.clone();
+    let path = path.as_str();
+
+    let diagnostics = variables::fill_in(&mut resolved, wineprefix_path);
+    if !diagnostics.is_empty() {
+        return Err(validate::report(path, &diagnostics));
+    }
+
+    // The filled in config is parsed from its tables, which has no positions in any of
+    // the files, so a mistake is found in them by the key it's at
+    let contents = toml::to_string(&resolved.table)
+        .with_context(|| format!("Merging config file {}", path))?;
+    let mut config: Config = toml::from_str(&contents).map_err(|e| {
+        let diagnostic = Diagnostic::from_resolved_parse_error(
+            &e,
+            &contents,
+            &resolved.files,
+            &resolved.origins,
+        );
+        validate::report(path, &[diagnostic])
+    })?;
+
+    let diagnostics = config.check(&resolved.files, &resolved.origins);
+    if !diagnostics.is_empty() {
+        return Err(validate::report(path, &diagnostics));
+    }
+
+    config.suffix = resolved.suffix;
+
+    Ok(config)
+}
+
+impl Config {
+    /// Everything wrong with a config that parsed fine. `files` are the config files it
+    /// came from and `origins` where each value is in them, to find the mistakes. Each
+    /// problem is about the key at a path like `wine.run.1.timeout`.
+    fn check(&self, files: &[SourceFile], origins: &BTreeMap<String, Origin>) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut problem = |message: String, path: &str| {
+            diagnostics.push(Diagnostic::new(message).at(files, origins, path));
+        };
+
+        let entrypoint = &self.app.entrypoint;
+        if entrypoint.program.is_empty() {
+            problem(
+                "app.entrypoint.program must be set to the program you want to launch".to_string(),
+                "app.entrypoint.program",
+            );
+        }
+        check_run(entrypoint, "app.entrypoint", &mut problem);
+        // The entrypoint starts on the user's Mac, where paths on the host don't exist
+        if let Some(cwd) = entrypoint.cwd.as_deref() {
+            let in_wineprefix = [WINEPREFIX_VARIABLE, DRIVE_C_VARIABLE]
+                .iter()
+                .any(|variable| cwd.starts_with(&format!("${{{}}}", variable)));
+            if !WindowsPath::is_windows_path(cwd) && !in_wineprefix {
+                problem(
+                    format!(
+                        "app.entrypoint.cwd {} is a path on this machine, the app needs a Windows path like c:/users or one that starts with ${{WINEPREFIX}} or ${{DRIVE_C}}",
+                        cwd
+                    ),
+                    "app.entrypoint.cwd",
+                );
+            }
+        }
+        for (index, run) in self.wine.runs.iter().enumerate() {
+            check_run(run, &format!("wine.run.{}", index), &mut problem);
+        }
+
+        if let Some(icon) = self.app.icon.as_deref().filter(|icon| *icon != "auto") {
+            if !Path::new(icon).is_file() {
+                problem(format!("The icon {} doesn't exist", icon), "app.icon");
+            }
+        }
+
+        for (index, entry) in self.get_persist().iter().enumerate() {
+            match entry {
+                Persist::Path { path } => {
+                    if let Err(e) = persisted_path(path, "user") {
+                        problem(format!("{:#}", e), &format!("app.persist.{}.path", index));
+                    }
+                }
+                Persist::Registry { registry } if registry.is_empty() => problem(
+                    "app.persist registry keys can't be empty".to_string(),
+                    &format!("app.persist.{}.registry", index),
+                ),
+                Persist::Registry { .. } => {}
+            }
+        }
+
+        // 32-bit wine can only run 32-bit wineprefixes
+        let arch = &self.wine.build.arch;
+        let prefix_arch = self.wine.prefix.prefix_arch;
+        if arch != "32" && arch != "64" {
+            problem(
+                format!(r#"arch is "32" or "64", not "{}""#, arch),
+                "wine.build.arch",
+            );
+        } else if arch == "32" && prefix_arch != PrefixArch::Win32 {
+            problem(
+                format!(
+                    r#"prefix_arch is "{}", but [wine.build] arch "32" can only make "win32" wineprefixes"#,
+                    prefix_arch.as_str()
+                ),
+                "wine.prefix.prefix_arch",
+            );
+        }
+
+        match self.get_wine_source() {
+            WineSource::Gcenx if arch == "32" => problem(
+                r#"gcenx only has 64-bit builds, arch has to be "64""#.to_string(),
+                "wine.source.type",
+            ),
+            WineSource::Tarball { path } if !Path::new(&path).is_file() => problem(
+                format!("The wine tarball {} doesn't exist", path),
+                "wine.source.path",
+            ),
+            WineSource::Directory { path } if !Path::new(&path).is_dir() => problem(
+                format!("The wine directory {} doesn't exist", path),
+                "wine.source.path",
+            ),
+            _ => {}
+        }
+
+        if let Some(sha256) = &self.wine.build.sha256 {
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                problem(
+                    format!("sha256 {} isn't 64 hexadecimal digits", sha256),
+                    "wine.build.sha256",
+                );
+            }
+        }
+
+        if let Some(base_prefix) = &self.wine.prefix.base_prefix {
+            if !Path::new(base_prefix).is_dir() {
+                problem(
+                    format!("The base prefix {} doesn't exist", base_prefix),
+                    "wine.prefix.base_prefix",
+                );
+            }
+        }
+
+        if let CompressWineprefix::WithLevel(CompressionWithLevel {
+            format,
+            level: Some(level),
+        }) = self.wine.prefix.compress_wineprefix
+        {
+            if let Some(format) = to_archive_format(format) {
+                let (min_level, max_level) = format.levels();
+                if level < min_level || level > max_level {
+                    problem(
+                        format!(
+                            "{:?} compression levels go from {} to {}, not {}",
+                            format, min_level, max_level, level
+                        ),
+                        "wine.prefix.compress_wineprefix.level",
+                    );
+                }
+            }
+        }
+
+        for pattern in self.wine.prefix.prune.iter().flatten() {
+            if !pattern.to_ascii_lowercase().starts_with("c:") {
+                problem(
+                    format!("prune pattern {} has to be on drive C:", pattern),
+                    "wine.prefix.prune",
+                );
+            }
+        }
+
+        for (index, volume) in self.wine.volumes.iter().enumerate() {
+            let path = format!("wine.volume.{}", index);
+            match WindowsPath::parse(&volume.to) {
+                Ok(to) if to.is_root() => problem(
+                    format!("A volume can't replace all of {}", to),
+                    &format!("{}.to", path),
+                ),
+                Ok(_) => {}
+                Err(e) => problem(
+                    format!("Volumes go into the wineprefix: {:#}", e),
+                    &format!("{}.to", path),
+                ),
+            }
+            if WindowsPath::is_windows_path(&volume.from) {
+                if let Err(e) = WindowsPath::parse(&volume.from) {
+                    problem(format!("{:#}", e), &format!("{}.from", path));
+                }
+            }
+
+            let mode = volume.mode.unwrap_or_default();
+            if mode != VolumeMode::Copy && (volume.file_mode.is_some() || volume.dir_mode.is_some())
+            {
+                problem(
+                    r#"file_mode and dir_mode only work with mode "copy", linked files are shared with the host"#
+                        .to_string(),
+                    &format!("{}.mode", path),
+                );
+            }
+        }
+
+        diagnostics
     }
 }
 
-pub fn load(path: String) -> Config {
-    let contents = fs::read_to_string(path).expect("Unable to read config file");
-    return toml::from_str(contents.as_str()).expect("Unable to parse config file");
+/// The mistakes an entrypoint or a program to install at `path` can have
+fn check_run(run: &Run, path: &str, problem: &mut impl FnMut(String, &str)) {
+    if let Some(cwd) = run.cwd.as_deref() {
+        if WindowsPath::is_windows_path(cwd) {
+            if let Err(e) = WindowsPath::parse(cwd) {
+                problem(format!("{:#}", e), &format!("{}.cwd", path));
+            }
+        }
+    }
+    if run.success_codes.as_ref().is_some_and(Vec::is_empty) {
+        problem(
+            format!("success_codes of {} can't be empty", run.program),
+            &format!("{}.success_codes", path),
+        );
+    }
+    if run.timeout == Some(0) {
+        problem(
+            format!("timeout of {} has to be at least a second", run.program),
+            &format!("{}.timeout", path),
+        );
+    }
+}
+
+fn to_archive_format(format: CompressionFormat) -> Option<Format> {
+    match format {
+        CompressionFormat::None => None,
+        CompressionFormat::Gzip => Some(Format::Gzip),
+        CompressionFormat::Xz => Some(Format::Xz),
+        CompressionFormat::Zstd => Some(Format::Zstd),
+    }
 }
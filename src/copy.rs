@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+/// Recursively copy the directory `from` to `to`, which must not exist yet.
+///
+/// Symlinks are recreated rather than followed. Wineprefixes link `dosdevices/z:` to
+/// `/`, so following them would try to copy the whole file system.
+pub fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir(to).with_context(|| format!("Creating {}", to.display()))?;
+
+    for entry in fs::read_dir(from).with_context(|| format!("Reading {}", from.display()))? {
+        let entry = entry?;
+        copy_path(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    // Permissions are copied last, so read-only directories can still be filled
+    let permissions = fs::metadata(from)?.permissions();
+    fs::set_permissions(to, permissions)
+        .with_context(|| format!("Setting permissions of {}", to.display()))?;
+
+    Ok(())
+}
+
+/// Copy whatever is at `from` to `to`, which must not exist yet: a file, a symlink, or
+/// a directory with everything in it
+pub fn copy_path(from: &Path, to: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(from)
+        .with_context(|| format!("Reading {}", from.display()))?
+        .file_type();
+
+    if file_type.is_dir() {
+        copy_dir(from, to)?;
+    } else if file_type.is_symlink() {
+        let target = fs::read_link(from)?;
+        symlink(&target, to).with_context(|| format!("Creating symlink {}", to.display()))?;
+    } else {
+        fs::copy(from, to)
+            .with_context(|| format!("Copying {} to {}", from.display(), to.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Remove `path` if it exists, whether it's a file, a symlink or a directory
+pub fn remove_path(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("Removing {}", path.display()))
+}
@@ -0,0 +1,595 @@
+mod pipeline;
+mod prune;
+mod volumes;
+mod wine;
+
+use crate::cache::DownloadCache;
+use crate::config::{self, PrefixArch};
+use crate::files::icon;
+use crate::files::info_plist;
+use crate::files::launch;
+use crate::files::manifest;
+use crate::process::BuildLog;
+use crate::resolve;
+use pipeline::Pipeline;
+
+use anyhow::{anyhow, bail, Context, Result};
+use boxwine::archive::{self, Compression};
+use boxwine::copy::{copy_dir, remove_path};
+use boxwine::interpolate::fill_in_wineprefix;
+use boxwine::launcher::wine_binary;
+use boxwine::manifest::MANIFEST_PATH;
+use boxwine::winpath::WindowsPath;
+use clap::Parser;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+const WINEPREFIX_DIR_NAME: &str = "wineprefix";
+const WINE_DIR_NAME: &str = "wine";
+const LAUNCH_EXECUTABLE_NAME: &str = "launch";
+
+/// Create a Mac app from a config file
+#[derive(Parser)]
+pub struct Create {
+    /// Path to config file
+    #[clap(short, long, default_value = "app.boxwine.toml")]
+    file: String,
+
+    /// Path where you want your app. A profile adds its suffix to the name, like
+    /// "My App-debug.app".
+    #[clap(short, long, default_value = "My App.app")]
+    output: String,
+
+    /// Build with the overrides of [profile.<name>] in the config
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Launcher binary to put in the app, defaults to the boxwine-launcher built
+    /// alongside boxwine
+    #[clap(long)]
+    launcher: Option<String>,
+
+    /// Start from scratch instead of reusing the steps of earlier builds. Reusing them
+    /// keeps two extra copies of the wineprefix in the build directory, from after the
+    /// winetricks verbs and after the volumes.
+    #[clap(long)]
+    clean: bool,
+
+    /// Fail instead of downloading anything that isn't in the download cache
+    #[clap(long)]
+    offline: bool,
+}
+
+pub fn create(opts: Create) -> Result<()> {
+    // validate options
+    if !opts.output.as_str().ends_with(".app") {
+        panic!(r#"output "{}" must end with ".app"#, opts.output);
+    }
+
+    // the profile can change the name of the app, so it comes before everything else
+    let mut resolved = resolve::resolve(&opts.file)?;
+    resolved.select_profile(opts.profile.as_deref())?;
+    let opts = Create {
+        output: profile_output(&opts.output, resolved.suffix.as_deref()),
+        ..opts
+    };
+
+    // everything boxwine needs while building the app lives next to it in .app.boxwine,
+    // which is kept around so the next build can pick up where this one left off
+    let build_path = PathBuf::from(format!("{}.boxwine", opts.output));
+
+    // load config file, where ${WINEPREFIX} is the wineprefix that's being built
+    let absolute_build_path = std::path::absolute(&build_path)
+        .with_context(|| "Getting absolute path to the build directory")?;
+    let config = &config::from_resolved(
+        resolved,
+        Some(&absolute_build_path.join(WINEPREFIX_DIR_NAME)),
+    )?;
+    if opts.clean {
+        remove_path(&build_path)?;
+    }
+    fs::create_dir_all(&build_path)
+        .with_context(|| format!("Creating {}", build_path.display()))?;
+    let build_path = build_path
+        .canonicalize()
+        .with_context(|| "Getting absolute path to the build directory")?;
+
+    let log = &BuildLog::create(build_path.join("logs"))?;
+    let wine_dir = build_path.join(WINE_DIR_NAME);
+    let wineprefix_path = build_path.join(WINEPREFIX_DIR_NAME);
+    let mut pipeline = Pipeline::open(&build_path, &wineprefix_path)?;
+
+    // downloaded wine comes from the download cache that every build shares
+    let wine_source = config.get_wine_source();
+    let wine_url = config.get_wine_url();
+    let mut cache = DownloadCache::open()?;
+
+    // the archive of the wineprefix only gets its real name in the app
+    let wineprefix_archive_path = build_path.join(format!("{}.archive", WINEPREFIX_DIR_NAME));
+
+    pipeline.run(|pipeline| {
+        // put wine into the wine directory
+        let wine_inputs = (
+            &wine_source,
+            &wine_url,
+            config.get_wine_sha256(),
+            wine::host_path(&wine_source).map(host_file_stamp),
+        );
+        pipeline.step("wine", &wine_inputs, || {
+            wine::install_wine(
+                log,
+                config,
+                &mut cache,
+                &build_path,
+                &wine_dir,
+                opts.offline,
+            )
+        })?;
+
+        // initialize the wineprefix
+        let base_prefix = config.get_base_prefix();
+        let wineprefix_inputs = (
+            base_prefix,
+            base_prefix.as_deref().map(host_file_stamp),
+            config.get_prefix_arch(),
+            config.get_wine_dll_overrides(),
+        );
+        pipeline.wineprefix_step("wineprefix", &wineprefix_inputs, || {
+            initialize_wineprefix(log, config, &wine_dir, &wineprefix_path)
+        })?;
+
+        // use winetricks to download/install verbs
+        let verbs_inputs = (config.get_verbs(), config.get_sandbox());
+        pipeline.wineprefix_step_with_snapshot("winetricks", &verbs_inputs, || {
+            install_winetricks_verbs(log, config, &wineprefix_path)
+        })?;
+
+        // Copy files/directories, pre-install
+        pipeline.wineprefix_step_with_snapshot(
+            "volumes",
+            &volume_inputs(config, false)?,
+            || volumes::place_volumes(config, &wineprefix_path, false),
+        )?;
+
+        // Install the programs
+        let run_inputs: Vec<_> = config
+            .get_runs()
+            .iter()
+            .map(|run| (run, host_file_stamp(&run.program)))
+            .collect();
+        pipeline.wineprefix_step("programs", &run_inputs, || {
+            install_programs(log, config, &wine_dir, &wineprefix_path)
+        })?;
+
+        // Copy files/directories, post-install
+        pipeline.wineprefix_step(
+            "post_install_volumes",
+            &volume_inputs(config, true)?,
+            || volumes::place_volumes(config, &wineprefix_path, true),
+        )?;
+
+        // Post-install
+        // remove what the app doesn't need from the wineprefix
+        let prune_patterns = config.get_prune_patterns();
+        pipeline.wineprefix_step("prune", &prune_patterns, || {
+            prune::prune_wineprefix(&prune_patterns, &wineprefix_path)
+        })?;
+
+        // compress the wineprefix if configured
+        let compression = config.get_wineprefix_compression();
+        pipeline.step("compress", &compression, || {
+            compress_wineprefix(compression, &wineprefix_path, &wineprefix_archive_path)
+        })?;
+
+        pipeline.finish()
+    })?;
+
+    // put the app together from everything in the build directory
+    assemble_app(
+        config,
+        &opts,
+        &build_path,
+        &wine_dir,
+        &wineprefix_path,
+        &wineprefix_archive_path,
+        pipeline.wineprefix_version(),
+    )
+}
+
+/// Every volume of a step, with the stamps of its files on the host
+type VolumeInputs<'a> = Vec<(&'a config::Volume, Vec<(PathBuf, HostFileStamp)>)>;
+
+/// The inputs of a volumes step, including what's on the host for every volume and
+/// when it last changed
+fn volume_inputs(config: &config::Config, post_install: bool) -> Result<VolumeInputs<'_>> {
+    config
+        .get_volumes()
+        .iter()
+        .filter(|volume| volume.post_install.unwrap_or_default() == post_install)
+        .map(|volume| {
+            let stamps = volumes::host_sources(volume)?
+                .into_iter()
+                .map(|source| {
+                    let stamp = host_file_stamp(&source.to_string_lossy());
+                    (source, stamp)
+                })
+                .collect();
+            Ok((volume, stamps))
+        })
+        .collect()
+}
+
+/// Size and modification time of a file or directory on the host
+type HostFileStamp = Option<(u64, SystemTime)>;
+
+/// The stamp of a file or directory on the host, so steps using it rerun when it
+/// changes. Windows paths and missing files have none.
+fn host_file_stamp(path: &str) -> HostFileStamp {
+    if WindowsPath::is_windows_path(path) {
+        return None;
+    }
+
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// The output with the suffix of the profile before `.app`, so apps built with different
+/// profiles don't replace each other
+fn profile_output(output: &str, suffix: Option<&str>) -> String {
+    match (output.strip_suffix(".app"), suffix) {
+        (Some(name), Some(suffix)) => format!("{}-{}.app", name, suffix),
+        _ => output.to_string(),
+    }
+}
+
+/// Put the app together in the build directory, then move it into place. That way a
+/// failed build never leaves a half-built app behind.
+fn assemble_app(
+    config: &config::Config,
+    opts: &Create,
+    build_path: &Path,
+    wine_dir: &Path,
+    wineprefix_path: &Path,
+    wineprefix_archive_path: &Path,
+    wineprefix_version: &str,
+) -> Result<()> {
+    let output_path = Path::new(&opts.output);
+    let app_name = output_path
+        .file_name()
+        .ok_or_else(|| anyhow!(r#"output "{}" has no file name"#, opts.output))?;
+
+    println!("Assembling {} ...", opts.output);
+    let app_path = build_path.join(app_name);
+    remove_path(&app_path)?;
+
+    create_app_bundle(config, &app_path, wineprefix_version, &opts.launcher)?;
+
+    let macos_path = app_path.join("Contents/MacOS");
+    copy_dir(wine_dir, &macos_path.join(WINE_DIR_NAME)).with_context(|| "Copying wine")?;
+
+    if let Some(compression) = config.get_wineprefix_compression() {
+        let archive_name = format!("{}.{}", WINEPREFIX_DIR_NAME, compression.format.extension());
+        fs::copy(wineprefix_archive_path, macos_path.join(archive_name))
+            .with_context(|| "Copying the compressed wineprefix")?;
+    } else {
+        copy_dir(wineprefix_path, &macos_path.join(WINEPREFIX_DIR_NAME))
+            .with_context(|| "Copying the wineprefix")?;
+    }
+
+    // The icon can come from the entrypoint, so it can only be made once everything is installed
+    create_app_icon_and_info_plist(config, wineprefix_path, &app_path)?;
+
+    // Only ever replace an app that an earlier build made
+    if fs::symlink_metadata(output_path).is_ok() {
+        if !output_path.join(MANIFEST_PATH).exists() {
+            bail!(
+                "{} already exists and wasn't made by boxwine, not replacing it",
+                output_path.display()
+            );
+        }
+        remove_path(output_path)?;
+    }
+
+    fs::rename(&app_path, output_path)
+        .with_context(|| format!("Moving the app to {}", output_path.display()))?;
+    println!("Done!");
+
+    Ok(())
+}
+
+/// Create app bundle
+fn create_app_bundle(
+    config: &config::Config,
+    app_path: &Path,
+    wineprefix_version: &str,
+    launcher_path: &Option<String>,
+) -> Result<()> {
+    fs::create_dir(app_path).with_context(|| format!("Creating {}", app_path.display()))?;
+
+    let contents_resources = app_path.join("Contents/Resources");
+    fs::create_dir_all(contents_resources)
+        .with_context(|| "Creating Contents/Resources directory")?;
+
+    let contents_macos = app_path.join("Contents/MacOS");
+    fs::create_dir_all(contents_macos).with_context(|| "Creating Contents/MacOS directory")?;
+
+    manifest::create_manifest(
+        config,
+        WINE_DIR_NAME,
+        WINEPREFIX_DIR_NAME,
+        wineprefix_version,
+        app_path,
+    )?;
+    launch::create_launch(launcher_path, LAUNCH_EXECUTABLE_NAME, app_path)
+}
+
+/// Create the app icon and the Info.plist that refers to it
+fn create_app_icon_and_info_plist(
+    config: &config::Config,
+    wineprefix_path: &Path,
+    app_path: &Path,
+) -> Result<()> {
+    let icon_file = match config.get_app_icon().as_deref() {
+        Some("auto") => {
+            create_icon_from_entrypoint(config, wineprefix_path, app_path)?;
+            Some(icon::ICON_FILE_NAME)
+        }
+        Some(icon_path) => {
+            icon::create_icon(Path::new(icon_path), app_path)?;
+            Some(icon::ICON_FILE_NAME)
+        }
+        // Without a configured icon, the entrypoint's icon is a best effort
+        None => match create_icon_from_entrypoint(config, wineprefix_path, app_path) {
+            Ok(()) => Some(icon::ICON_FILE_NAME),
+            Err(e) => {
+                println!("Not using an app icon: {:#}", e);
+                None
+            }
+        },
+    };
+
+    info_plist::create_info_plist(config, LAUNCH_EXECUTABLE_NAME, icon_file, app_path)
+}
+
+fn create_icon_from_entrypoint(
+    config: &config::Config,
+    wineprefix_path: &Path,
+    app_path: &Path,
+) -> Result<()> {
+    // The launcher fills in the wineprefix variables of the entrypoint, the build's
+    // wineprefix stands in for the user's here
+    let program = fill_in_wineprefix(&config.get_entrypoint().program, wineprefix_path);
+    let exe_path = resolve_in_wineprefix(&program, wineprefix_path)?;
+
+    icon::create_icon_from_exe(&exe_path, app_path)
+}
+
+/// Find `path` on the host. Windows paths like `c:/app/app.exe` are looked up in the
+/// wineprefix, anything else is taken to be a path on the host.
+fn resolve_in_wineprefix(path: &str, wineprefix_path: &Path) -> Result<PathBuf> {
+    if WindowsPath::is_windows_path(path) {
+        Ok(WindowsPath::parse(path)?.host_path(wineprefix_path))
+    } else {
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// Create wineprefix
+fn initialize_wineprefix(
+    log: &BuildLog,
+    config: &config::Config,
+    wine_dir: &Path,
+    wineprefix_path: &Path,
+) -> Result<()> {
+    // if the user defined a base prefix, copy it over
+    match config.get_base_prefix() {
+        Some(b) => copy_wineprefix(b, config.get_prefix_arch(), wineprefix_path),
+        None => create_wineprefix(log, config, wine_dir, wineprefix_path),
+    }
+}
+
+fn copy_wineprefix(
+    base_prefix: &String,
+    prefix_arch: PrefixArch,
+    wineprefix_path: &Path,
+) -> Result<()> {
+    check_base_prefix_arch(Path::new(base_prefix), prefix_arch)?;
+
+    copy_dir(Path::new(base_prefix), wineprefix_path).with_context(|| {
+        format!(
+            "Copying base wineprefix from {} to {}",
+            base_prefix,
+            wineprefix_path.display()
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Make sure a base prefix was made for the architecture the config asks for. wineboot
+/// records it as `#arch=win32` or `#arch=win64` in `system.reg`, wow64 prefixes are win64.
+fn check_base_prefix_arch(base_prefix: &Path, prefix_arch: PrefixArch) -> Result<()> {
+    let system_reg_path = base_prefix.join("system.reg");
+    let system_reg = fs::read(&system_reg_path)
+        .with_context(|| format!("Reading {}", system_reg_path.display()))?;
+    let system_reg = String::from_utf8_lossy(&system_reg);
+
+    let base_arch = match system_reg
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("#arch="))
+    {
+        Some(base_arch) => base_arch,
+        // Wineprefixes from old versions of wine don't say
+        None => return Ok(()),
+    };
+    let expected_arch = match prefix_arch {
+        PrefixArch::Win32 => "win32",
+        PrefixArch::Win64 | PrefixArch::Wow64 => "win64",
+    };
+
+    if base_arch != expected_arch {
+        bail!(
+            r#"The base prefix {} is {}, but prefix_arch is "{}""#,
+            base_prefix.display(),
+            base_arch,
+            prefix_arch.as_str()
+        );
+    }
+
+    Ok(())
+}
+
+fn create_wineprefix(
+    log: &BuildLog,
+    config: &config::Config,
+    wine_dir: &Path,
+    wineprefix_path: &Path,
+) -> Result<()> {
+    let wineboot_path = wine_dir.join("bin/wineboot");
+
+    log.run(
+        "wineprefix",
+        Command::new(wineboot_path)
+            .arg("-u")
+            .env("WINEPREFIX", wineprefix_path)
+            .env("WINEARCH", config.get_prefix_arch().as_str())
+            .env("WINEDLLOVERRIDES", config.get_wine_dll_overrides()),
+    )
+    .with_context(|| format!("Creating wineprefix at {}", wineprefix_path.display()))?;
+
+    Ok(())
+}
+
+/// Install winetricks verbs
+fn install_winetricks_verbs(
+    log: &BuildLog,
+    config: &config::Config,
+    wineprefix_path: &Path,
+) -> Result<()> {
+    let mut verbs: Vec<String> = config.get_verbs().clone();
+
+    // also sandbox the prefix if we want to
+    if *config.get_sandbox() {
+        verbs.push("sandbox".to_string());
+    }
+
+    // install verbs
+    log.run(
+        "winetricks",
+        Command::new("winetricks")
+            .args(verbs)
+            .env("WINEPREFIX", wineprefix_path)
+            .env("WINEARCH", config.get_prefix_arch().as_str())
+            .env("WINEDLLOVERRIDES", config.get_wine_dll_overrides()),
+    )
+    .with_context(|| "Installing verbs")?;
+
+    Ok(())
+}
+
+fn install_programs(
+    log: &BuildLog,
+    config: &config::Config,
+    wine_dir: &Path,
+    wineprefix_path: &Path,
+) -> Result<()> {
+    let wineserver = |arg| {
+        let mut command = Command::new(wine_dir.join("bin/wineserver"));
+        command.arg(arg).env("WINEPREFIX", wineprefix_path);
+        command
+    };
+
+    for run in config.get_runs() {
+        if let Err(e) = install_program(log, config, wine_dir, wineprefix_path, run) {
+            // don't leave anything the program started running in the wineprefix
+            let _ = wineserver("-k").status();
+            return Err(e).with_context(|| format!("Running program {}", run.program));
+        }
+    }
+
+    // installers like to start other installers and quit, so wait for everything in the
+    // wineprefix to finish before the next step changes it
+    log.run("programs", &mut wineserver("-w"))
+        .with_context(|| "Waiting for the installed programs to finish")
+}
+
+fn install_program(
+    log: &BuildLog,
+    config: &config::Config,
+    wine_dir: &Path,
+    wineprefix_path: &Path,
+    run: &config::Run,
+) -> Result<()> {
+    let mut command = Command::new(wine_binary(wine_dir));
+    command.arg("start");
+    if run.wait.unwrap_or(true) {
+        command.arg("/wait");
+    }
+
+    // programs on the host are started by their absolute path, so the working
+    // directory can be anywhere. Anything else is a Windows program wine finds by its
+    // name, like regedit.
+    if WindowsPath::is_windows_path(&run.program) {
+        command.arg(WindowsPath::parse(&run.program)?.to_string());
+    } else if Path::new(&run.program).is_absolute() {
+        let program_path = Path::new(&run.program)
+            .canonicalize()
+            .with_context(|| format!("Finding {}", run.program))?;
+        command.arg("/unix").arg(program_path);
+    } else {
+        command.arg(&run.program);
+    }
+    command.args(run.args.iter().flatten());
+
+    if let Some(cwd) = &run.cwd {
+        let cwd_path = resolve_in_wineprefix(cwd, wineprefix_path)?;
+        if !cwd_path.is_dir() {
+            bail!("The working directory {} doesn't exist", cwd);
+        }
+        command.current_dir(cwd_path);
+    }
+
+    command
+        .envs(run.env.iter().flatten())
+        .env("WINEPREFIX", wineprefix_path)
+        .env("WINEARCH", config.get_prefix_arch().as_str());
+
+    let timeout = run.timeout.map(Duration::from_secs);
+    let success_codes = run.success_codes.as_deref().unwrap_or(&[0]);
+    log.run_with("programs", &mut command, timeout, success_codes)
+}
+
+fn compress_wineprefix(
+    compression: Option<Compression>,
+    wineprefix_path: &Path,
+    wineprefix_archive_path: &Path,
+) -> Result<()> {
+    remove_path(wineprefix_archive_path)?;
+
+    if let Some(compression) = compression {
+        archive::pack(wineprefix_path, wineprefix_archive_path, compression)
+            .with_context(|| "Compressing wineprefix")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_add_their_suffix_to_the_app_name() {
+        assert_eq!(
+            profile_output("My App.app", Some("debug")),
+            "My App-debug.app"
+        );
+        assert_eq!(profile_output("My App.app", None), "My App.app");
+        assert_eq!(
+            profile_output("out/Game.app", Some("beta")),
+            "out/Game-beta.app"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context, Result};
+use boxwine::copy::{copy_dir, remove_path};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STATE_FILE_NAME: &str = "state.toml";
+const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+/// How many characters of a fingerprint make up a wineprefix version
+const WINEPREFIX_VERSION_LEN: usize = 16;
+
+/// What the staging area looked like at the end of the last build
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    /// The step whose result the working wineprefix holds. Empty when the wineprefix
+    /// doesn't exist or a step failed half way through changing it.
+    wineprefix: Option<String>,
+
+    /// Steps that completed, in order
+    #[serde(default)]
+    steps: Vec<CompletedStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompletedStep {
+    name: String,
+    fingerprint: String,
+}
+
+/// Runs the steps of a build in a staging directory, and skips the ones that already
+/// ran with the same inputs in an earlier build.
+///
+/// Every step has a fingerprint made from its inputs and the fingerprint of the step
+/// before it, so changing one step reruns it and everything after it. A snapshot is a
+/// full copy of the wineprefix, so only some of the steps that change it keep one. When
+/// a step changed and the wineprefix from before it has no snapshot, the build goes back
+/// to the last snapshot and reruns the steps after it.
+pub struct Pipeline {
+    build_path: PathBuf,
+    wineprefix_path: PathBuf,
+    state: State,
+
+    /// Number of steps seen so far in this build
+    step_count: usize,
+
+    /// Fingerprint of the step before the next one
+    fingerprint: String,
+
+    /// Whether every step so far was reused from an earlier build
+    reusing: bool,
+
+    /// The last wineprefix step so far, which the wineprefix should be at
+    last_wineprefix_step: Option<String>,
+
+    /// Fingerprint of the last wineprefix step so far
+    wineprefix_fingerprint: String,
+
+    /// Index of the first wineprefix step after the last snapshot so far, if any
+    unsnapshotted_since: Option<usize>,
+}
+
+/// Stops a pass over the steps that needs a wineprefix there's no snapshot of, after
+/// the steps since the last snapshot were forgotten
+#[derive(Debug)]
+struct Rewind;
+
+impl Display for Rewind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Going back to the last snapshot of the wineprefix")
+    }
+}
+
+impl std::error::Error for Rewind {}
+
+impl Pipeline {
+    pub fn open(build_path: &Path, wineprefix_path: &Path) -> Result<Pipeline> {
+        let state_path = build_path.join(STATE_FILE_NAME);
+        let state = if state_path.exists() {
+            let contents = fs::read_to_string(&state_path)
+                .with_context(|| format!("Reading {}", state_path.display()))?;
+            toml::from_str(&contents)
+                .with_context(|| format!("Parsing {}", state_path.display()))?
+        } else {
+            State::default()
+        };
+
+        Ok(Pipeline {
+            build_path: build_path.to_path_buf(),
+            wineprefix_path: wineprefix_path.to_path_buf(),
+            state,
+            step_count: 0,
+            fingerprint: String::new(),
+            reusing: true,
+            last_wineprefix_step: None,
+            wineprefix_fingerprint: String::new(),
+            unsnapshotted_since: None,
+        })
+    }
+
+    /// Go through the steps of the build, which `steps` runs on the pipeline in order.
+    /// They're gone through a second time when a step needs the wineprefix from a step
+    /// without a snapshot.
+    pub fn run(&mut self, mut steps: impl FnMut(&mut Pipeline) -> Result<()>) -> Result<()> {
+        loop {
+            match steps(self) {
+                Err(error) if error.is::<Rewind>() => {
+                    self.step_count = 0;
+                    self.fingerprint = String::new();
+                    self.reusing = true;
+                    self.last_wineprefix_step = None;
+                    self.wineprefix_fingerprint = String::new();
+                    self.unsnapshotted_since = None;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Run a step that produces files of its own in the staging directory. It has to
+    /// replace whatever an earlier run of it left behind.
+    pub fn step<T: Debug>(
+        &mut self,
+        name: &str,
+        inputs: &T,
+        run: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if self.reuse(name, inputs) {
+            return Ok(());
+        }
+
+        self.restore_wineprefix()?;
+        run()?;
+        self.complete(name)
+    }
+
+    /// Run a step that changes the wineprefix
+    pub fn wineprefix_step<T: Debug>(
+        &mut self,
+        name: &str,
+        inputs: &T,
+        run: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        self.run_wineprefix_step(name, inputs, false, run)
+    }
+
+    /// Run a step that changes the wineprefix, and keep a snapshot of the wineprefix
+    /// after it for later builds that change a step after it
+    pub fn wineprefix_step_with_snapshot<T: Debug>(
+        &mut self,
+        name: &str,
+        inputs: &T,
+        run: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        self.run_wineprefix_step(name, inputs, true, run)
+    }
+
+    fn run_wineprefix_step<T: Debug>(
+        &mut self,
+        name: &str,
+        inputs: &T,
+        snapshot: bool,
+        run: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let index = self.step_count;
+        let reused = self.reuse(name, inputs);
+        if !reused {
+            self.restore_wineprefix()?;
+        }
+
+        self.unsnapshotted_since = match snapshot {
+            true => None,
+            false => self.unsnapshotted_since.or(Some(index)),
+        };
+        if reused {
+            self.last_wineprefix_step = Some(name.to_string());
+            self.wineprefix_fingerprint = self.fingerprint.clone();
+            return Ok(());
+        }
+
+        self.state.wineprefix = None;
+        self.save()?;
+
+        run()?;
+
+        // A snapshot left from an earlier build that kept one is out of date either way
+        let snapshot_path = self.snapshot_path(name);
+        remove_path(&snapshot_path)?;
+        if snapshot {
+            copy_dir(&self.wineprefix_path, &snapshot_path)
+                .with_context(|| format!("Saving a snapshot of the wineprefix after {}", name))?;
+        }
+
+        self.state.wineprefix = Some(name.to_string());
+        self.last_wineprefix_step = Some(name.to_string());
+        self.wineprefix_fingerprint = self.fingerprint.clone();
+        self.complete(name)
+    }
+
+    /// Identifies what's in the wineprefix: builds that ran the same wineprefix steps
+    /// with the same inputs have the same version
+    pub fn wineprefix_version(&self) -> &str {
+        &self.wineprefix_fingerprint
+            [..WINEPREFIX_VERSION_LEN.min(self.wineprefix_fingerprint.len())]
+    }
+
+    /// Make sure the wineprefix is in the state the last step left it in
+    pub fn finish(&mut self) -> Result<()> {
+        self.restore_wineprefix()
+    }
+
+    /// Whether the step can be skipped, because it ran with the same inputs after the
+    /// same steps last time. Once a step can't be skipped, none of the following can be.
+    fn reuse<T: Debug>(&mut self, name: &str, inputs: &T) -> bool {
+        let index = self.step_count;
+        self.step_count += 1;
+        self.fingerprint = fingerprint(&self.fingerprint, name, inputs);
+
+        if self.reusing {
+            if let Some(step) = self.state.steps.get(index) {
+                if step.name == name && step.fingerprint == self.fingerprint {
+                    println!("Reusing {} from an earlier build", name);
+                    return true;
+                }
+            }
+
+            self.reusing = false;
+            self.state.steps.truncate(index);
+        }
+
+        println!("Running {} ...", name);
+        false
+    }
+
+    fn complete(&mut self, name: &str) -> Result<()> {
+        self.state.steps.push(CompletedStep {
+            name: name.to_string(),
+            fingerprint: self.fingerprint.clone(),
+        });
+        self.save()?;
+        println!("Done!");
+
+        Ok(())
+    }
+
+    /// Put the wineprefix back in the state after the last wineprefix step, either
+    /// from its snapshot or by removing it if there wasn't one. Without a snapshot of
+    /// that step, the steps since the last snapshot are forgotten and the build has to
+    /// go through its steps again.
+    fn restore_wineprefix(&mut self) -> Result<()> {
+        if self.last_wineprefix_step.is_some() && self.state.wineprefix == self.last_wineprefix_step
+        {
+            return Ok(());
+        }
+
+        if let Some(index) = self.unsnapshotted_since {
+            println!(
+                "There's no snapshot of the wineprefix after {}, running the steps since the last snapshot again",
+                self.last_wineprefix_step.as_deref().unwrap_or_default()
+            );
+            self.state.steps.truncate(index);
+            self.state.wineprefix = None;
+            self.save()?;
+            return Err(Rewind.into());
+        }
+
+        remove_path(&self.wineprefix_path)?;
+        if let Some(step) = &self.last_wineprefix_step {
+            println!("Restoring the wineprefix from after {} ...", step);
+            copy_dir(&self.snapshot_path(step), &self.wineprefix_path)
+                .with_context(|| format!("Restoring the wineprefix from after {}", step))?;
+        }
+
+        self.state.wineprefix = self.last_wineprefix_step.clone();
+        self.save()
+    }
+
+    fn snapshot_path(&self, step: &str) -> PathBuf {
+        self.build_path.join(SNAPSHOTS_DIR_NAME).join(step)
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(self.build_path.join(SNAPSHOTS_DIR_NAME))?;
+
+        let state_path = self.build_path.join(STATE_FILE_NAME);
+        let contents = toml::to_string(&self.state).with_context(|| "Serializing build state")?;
+        fs::write(&state_path, contents)
+            .with_context(|| format!("Writing {}", state_path.display()))
+    }
+}
+
+/// Hash of the step's name and inputs, chained to the fingerprint of the step before it
+fn fingerprint<T: Debug>(previous: &str, name: &str, inputs: &T) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(name.as_bytes());
+    hasher.update(format!("{:?}", inputs).as_bytes());
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Build with steps like the real ones, each writing its input to a file in the
+    /// wineprefix. Returns the steps that ran.
+    fn build(build_path: &Path, inputs: [&str; 4]) -> Vec<&'static str> {
+        let wineprefix_path = build_path.join("wineprefix");
+        let ran = RefCell::new(Vec::new());
+        let write = |name: &'static str, input: &str| {
+            ran.borrow_mut().push(name);
+            if name == "wineprefix" {
+                fs::create_dir(&wineprefix_path)?;
+            }
+            fs::write(wineprefix_path.join(name), input)?;
+            Ok(())
+        };
+
+        let mut pipeline = Pipeline::open(build_path, &wineprefix_path).unwrap();
+        pipeline
+            .run(|pipeline| {
+                pipeline
+                    .wineprefix_step("wineprefix", &inputs[0], || write("wineprefix", inputs[0]))?;
+                pipeline.wineprefix_step_with_snapshot("winetricks", &inputs[1], || {
+                    write("winetricks", inputs[1])
+                })?;
+                pipeline
+                    .wineprefix_step("programs", &inputs[2], || write("programs", inputs[2]))?;
+                pipeline.wineprefix_step("prune", &inputs[3], || write("prune", inputs[3]))?;
+                pipeline.finish()
+            })
+            .unwrap();
+
+        for (name, input) in ["wineprefix", "winetricks", "programs", "prune"]
+            .iter()
+            .zip(inputs)
+        {
+            assert_eq!(
+                fs::read_to_string(wineprefix_path.join(name)).unwrap(),
+                input
+            );
+        }
+        ran.into_inner()
+    }
+
+    #[test]
+    fn snapshots_only_some_steps_and_reruns_from_the_last_one() {
+        let build_dir = tempfile::tempdir().unwrap();
+        let build_path = build_dir.path();
+
+        assert_eq!(
+            build(build_path, ["a", "b", "c", "d"]),
+            ["wineprefix", "winetricks", "programs", "prune"]
+        );
+        let snapshots = fs::read_dir(build_path.join(SNAPSHOTS_DIR_NAME)).unwrap();
+        let snapshots: Vec<_> = snapshots.map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(snapshots, ["winetricks"]);
+
+        assert!(build(build_path, ["a", "b", "c", "d"]).is_empty());
+
+        // The wineprefix after programs has no snapshot, so programs runs again from the
+        // snapshot after winetricks
+        assert_eq!(
+            build(build_path, ["a", "b", "c", "e"]),
+            ["programs", "prune"]
+        );
+
+        // Without any snapshot before it, the wineprefix starts over
+        assert_eq!(
+            build(build_path, ["a", "f", "c", "e"]),
+            ["wineprefix", "winetricks", "programs", "prune"]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{bail, Context, Result};
+use boxwine::copy::remove_path;
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::path::{Component, Path};
+
+/// Windows paths aren't case sensitive, so neither are the patterns
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Remove everything in the wineprefix that matches one of `patterns`, like
+/// `c:/windows/Installer/*` or `c:/**/*.log`, and show how much each one saved
+pub fn prune_wineprefix(patterns: &[String], wineprefix_path: &Path) -> Result<()> {
+    let drive_c = wineprefix_path.join("drive_c");
+    let canonical_drive_c = drive_c
+        .canonicalize()
+        .with_context(|| format!("Finding {}", drive_c.display()))?;
+
+    // Check every pattern before anything is removed
+    let host_patterns = patterns
+        .iter()
+        .map(|pattern| host_pattern(pattern, &drive_c))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (pattern, host_pattern) in patterns.iter().zip(host_patterns) {
+        let matches = glob::glob_with(&host_pattern, MATCH_OPTIONS)
+            .with_context(|| format!("Invalid prune pattern {}", pattern))?;
+
+        let mut saved = 0;
+        for path in matches {
+            let path = path.with_context(|| format!("Pruning {}", pattern))?;
+
+            // Directories come before what's in them, which is gone by the time it
+            // comes up
+            if fs::symlink_metadata(&path).is_err() {
+                continue;
+            }
+
+            // Links in the wineprefix can lead anywhere on the host, like the user
+            // folders that point into the home directory. Only remove what's really in it.
+            let in_wineprefix = path
+                .parent()
+                .and_then(|parent| parent.canonicalize().ok())
+                .is_some_and(|parent| parent.starts_with(&canonical_drive_c));
+            if !in_wineprefix {
+                continue;
+            }
+
+            saved += disk_usage(&path)?;
+            remove_path(&path)?;
+        }
+
+        println!("Pruning {} saved {}", pattern, format_size(saved));
+    }
+
+    Ok(())
+}
+
+/// Turn a pattern on drive C: into one that matches the files in `drive_c`
+fn host_pattern(pattern: &str, drive_c: &Path) -> Result<String> {
+    let mut chars = pattern.chars();
+    let rest = match (chars.next(), chars.next()) {
+        (Some('c' | 'C'), Some(':')) => chars.as_str().replace('\\', "/"),
+        _ => bail!(
+            "Can't prune {}, patterns have to be on drive C: like c:/windows/temp/*",
+            pattern
+        ),
+    };
+    if Path::new(&rest)
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        bail!("Can't prune {}, patterns can't have .. in them", pattern);
+    }
+
+    let host_pattern = format!(
+        "{}/{}",
+        Pattern::escape(&drive_c.to_string_lossy()),
+        rest.trim_start_matches('/')
+    );
+    Pattern::new(&host_pattern).with_context(|| format!("Invalid prune pattern {}", pattern))?;
+
+    Ok(host_pattern)
+}
+
+/// Bytes taken up by `path` and everything in it, without following links
+fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Reading {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut usage = metadata.len();
+    for entry in fs::read_dir(path).with_context(|| format!("Reading {}", path.display()))? {
+        usage += disk_usage(&entry?.path())?;
+    }
+
+    Ok(usage)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["bytes", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,265 @@
+use crate::config::{self, Volume, VolumeMode};
+
+use anyhow::{bail, Context, Result};
+use boxwine::copy::remove_path;
+use boxwine::winpath::WindowsPath;
+use glob::Pattern;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Put the volumes that go in before or after the programs are installed into the
+/// wineprefix
+pub fn place_volumes(
+    config: &config::Config,
+    wineprefix_path: &Path,
+    post_install: bool,
+) -> Result<()> {
+    for volume in config.get_volumes() {
+        if volume.post_install.unwrap_or_default() == post_install {
+            place_volume(volume, wineprefix_path)
+                .with_context(|| format!("Copying volume {} to {}", volume.from, volume.to))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The files and directories on the host a volume takes its contents from. A glob
+/// matches any number of them, anything else is a single path that may not exist yet.
+/// Volumes from the wineprefix have none.
+pub fn host_sources(volume: &Volume) -> Result<Vec<PathBuf>> {
+    if WindowsPath::is_windows_path(&volume.from) {
+        return Ok(vec![]);
+    }
+    if !is_glob(&volume.from) {
+        return Ok(vec![PathBuf::from(&volume.from)]);
+    }
+
+    let mut sources = glob::glob(&volume.from)
+        .with_context(|| format!("Invalid volume pattern {}", volume.from))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Looking for {}", volume.from))?;
+    sources.sort();
+
+    Ok(sources)
+}
+
+fn place_volume(volume: &Volume, wineprefix_path: &Path) -> Result<()> {
+    let mode = volume.mode.unwrap_or_default();
+
+    // volumes can only go into the wineprefix
+    let to_path = WindowsPath::parse(&volume.to)
+        .with_context(|| format!("Invalid volume destination {}", volume.to))?;
+    if to_path.is_root() {
+        bail!("A volume can't replace all of {}", to_path);
+    }
+    let to_wineprefix = to_path.host_path(wineprefix_path);
+
+    let excludes = volume
+        .exclude
+        .iter()
+        .flatten()
+        .map(|exclude| {
+            Pattern::new(exclude).with_context(|| format!("Invalid exclude pattern {}", exclude))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Every match of a glob goes into `to`, a single path becomes `to`. Excludes are
+    // matched against paths in the directory the glob starts from, or in the single path.
+    let placements = if WindowsPath::is_windows_path(&volume.from) {
+        let from_path = WindowsPath::parse(&volume.from)?.host_path(wineprefix_path);
+        vec![(from_path, to_wineprefix)]
+    } else if is_glob(&volume.from) {
+        let sources = host_sources(volume)?;
+        if sources.is_empty() {
+            bail!("{} doesn't match anything", volume.from);
+        }
+        sources
+            .into_iter()
+            .filter_map(|source| {
+                let destination = to_wineprefix.join(source.file_name()?);
+                Some((source, destination))
+            })
+            .collect()
+    } else {
+        vec![(PathBuf::from(&volume.from), to_wineprefix)]
+    };
+
+    let glob_base = glob_base(&volume.from);
+    for (source, destination) in placements {
+        let relative_source = match (is_glob(&volume.from), source.strip_prefix(&glob_base)) {
+            (true, Ok(relative_source)) => relative_source.to_path_buf(),
+            _ => PathBuf::new(),
+        };
+        if !relative_source.as_os_str().is_empty() && is_excluded(&excludes, &relative_source)
+        {
+            continue;
+        }
+        place_tree(
+            volume,
+            mode,
+            &excludes,
+            &source,
+            &relative_source,
+            &destination,
+        )?;
+    }
+
+    // The directory the matches of a glob went into belongs to the volume too
+    if let (true, Some(dir_mode)) = (is_glob(&volume.from), volume.dir_mode) {
+        set_mode(&to_path.host_path(wineprefix_path), dir_mode)?;
+    }
+
+    Ok(())
+}
+
+/// Put `source` and everything in it that isn't excluded at `destination`, replacing
+/// files that are already there and merging into directories that are. Excludes are
+/// matched against paths in `source` prefixed with `relative_source`, its path in the
+/// directory a glob starts from.
+fn place_tree(
+    volume: &Volume,
+    mode: VolumeMode,
+    excludes: &[Pattern],
+    source: &Path,
+    relative_source: &Path,
+    destination: &Path,
+) -> Result<()> {
+    // Linked files point at absolute paths, so links work from anywhere in the wineprefix
+    let source = &source
+        .canonicalize()
+        .with_context(|| format!("Finding {}", source.display()))?;
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+    }
+
+    let entries = WalkDir::new(source).into_iter().filter_entry(|entry| {
+        let relative_path = entry.path().strip_prefix(source).unwrap_or(entry.path());
+        entry.depth() == 0 || !is_excluded(excludes, &relative_source.join(relative_path))
+    });
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Reading {}", source.display()))?;
+        let relative_path = entry.path().strip_prefix(source)?;
+        let target = if relative_path.as_os_str().is_empty() {
+            destination.to_path_buf()
+        } else {
+            destination.join(relative_path)
+        };
+        let file_type = entry.file_type();
+
+        if file_type.is_dir() {
+            if !fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_dir()) {
+                remove_path(&target)?;
+                fs::create_dir(&target)
+                    .with_context(|| format!("Creating {}", target.display()))?;
+            }
+            dirs.push(target);
+            continue;
+        }
+
+        // Never write through whatever is there already, it might be a link to the host
+        remove_path(&target)?;
+        if file_type.is_symlink() {
+            let link_target = fs::read_link(entry.path())?;
+            symlink(&link_target, &target)
+        } else {
+            match mode {
+                VolumeMode::Copy => fs::copy(entry.path(), &target).map(|_| ()),
+                VolumeMode::Symlink => symlink(entry.path(), &target),
+                VolumeMode::Hardlink => fs::hard_link(entry.path(), &target),
+            }
+        }
+        .with_context(|| format!("Placing {} at {}", entry.path().display(), target.display()))?;
+
+        if let (VolumeMode::Copy, Some(file_mode), false) =
+            (mode, volume.file_mode, file_type.is_symlink())
+        {
+            set_mode(&target, file_mode)?;
+        }
+    }
+
+    // Directories last, so read-only ones could still be filled
+    if let Some(dir_mode) = volume.dir_mode {
+        for dir in dirs.iter().rev() {
+            set_mode(dir, dir_mode)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an exclude pattern matches the path of an entry in the volume, or
+/// just its name, so `.DS_Store` is excluded everywhere
+fn is_excluded(excludes: &[Pattern], relative_path: &Path) -> bool {
+    let file_name = relative_path.file_name().map(Path::new);
+
+    excludes.iter().any(|exclude| {
+        exclude.matches_path(relative_path)
+            || file_name.is_some_and(|file_name| exclude.matches_path(file_name))
+    })
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Setting permissions of {}", path.display()))
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The directory a glob starts from, the parts of it before the first one with a
+/// wildcard
+fn glob_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_globbed_matches_by_name_and_by_path_from_the_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        for file in [".DS_Store", "a.txt", "raw/r.bin", "sub/.DS_Store", "sub/b.txt"] {
+            let path = assets.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        let wineprefix_path = dir.path().join("wineprefix");
+        fs::create_dir_all(wineprefix_path.join("drive_c")).unwrap();
+
+        let volume = Volume {
+            from: format!("{}/*", assets.display()),
+            to: "c:/assets".to_string(),
+            post_install: None,
+            exclude: Some(vec![".DS_Store".to_string(), "raw/**".to_string()]),
+            mode: None,
+            file_mode: None,
+            dir_mode: None,
+        };
+        place_volume(&volume, &wineprefix_path).unwrap();
+
+        let placed = wineprefix_path.join("drive_c/assets");
+        assert!(placed.join("a.txt").exists());
+        assert!(placed.join("sub/b.txt").exists());
+        assert!(!placed.join(".DS_Store").exists());
+        assert!(!placed.join("sub/.DS_Store").exists());
+        assert!(!placed.join("raw/r.bin").exists());
+    }
+
+    #[test]
+    fn globs_start_from_the_parts_without_wildcards() {
+        assert_eq!(glob_base("/on/host/assets/*"), Path::new("/on/host/assets"));
+        assert_eq!(glob_base("assets/*/textures/*.png"), Path::new("assets"));
+        assert_eq!(glob_base("*.txt"), Path::new(""));
+    }
+}
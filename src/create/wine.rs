@@ -0,0 +1,246 @@
+use crate::cache::{sha256_file, url_file_name, DownloadCache};
+use crate::config::{self, WineSource};
+use crate::download::download;
+use crate::process::BuildLog;
+
+use anyhow::{anyhow, bail, Context, Result};
+use boxwine::archive;
+use boxwine::copy::{copy_dir, remove_path};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How many directories deep to look for wine in builds with no known layout
+const SEARCH_DEPTH: usize = 4;
+
+/// The host file or directory a wine source reads from, if it doesn't download anything
+pub fn host_path(source: &WineSource) -> Option<&str> {
+    match source {
+        WineSource::Tarball { path } | WineSource::Directory { path } => Some(path),
+        _ => None,
+    }
+}
+
+/// Get wine from wherever the config says, and put it in `wine_dir` so its binaries
+/// are in `wine_dir/bin`
+pub fn install_wine(
+    log: &BuildLog,
+    config: &config::Config,
+    cache: &mut DownloadCache,
+    build_path: &Path,
+    wine_dir: &Path,
+    offline: bool,
+) -> Result<()> {
+    let source = config.get_wine_source();
+    let sha256 = config.get_wine_sha256();
+
+    let archive_path = match &source {
+        WineSource::Directory { path } => {
+            let wine_root = find_wine_root(&source, Path::new(path))?;
+            remove_path(wine_dir)?;
+            return copy_dir(&wine_root, wine_dir)
+                .with_context(|| format!("Copying wine from {}", wine_root.display()));
+        }
+        WineSource::Tarball { path } => {
+            verify_sha256(Path::new(path), path, sha256)?;
+            PathBuf::from(path)
+        }
+        _ => {
+            let url = config
+                .get_wine_url()
+                .ok_or_else(|| anyhow!("No URL to download wine from"))?;
+            fetch_wine(cache, &url, sha256, offline)?
+        }
+    };
+
+    extract_wine(log, &source, build_path, &archive_path, wine_dir)
+}
+
+/// Path of the wine tarball at `url` in the download cache, downloading it first if
+/// it isn't there yet. Either way it's checked against `sha256`.
+fn fetch_wine(
+    cache: &mut DownloadCache,
+    url: &str,
+    sha256: Option<&str>,
+    offline: bool,
+) -> Result<PathBuf> {
+    if let Some(tarball_path) = cache.lookup(url, sha256)? {
+        println!("Using {} from the download cache", url);
+        verify_sha256(&tarball_path, url, sha256)?;
+        return Ok(tarball_path);
+    }
+    if offline {
+        bail!(
+            "{} isn't in the download cache and --offline was given, add it with `boxwine cache import`",
+            url
+        );
+    }
+
+    // An earlier download that was interrupted is picked up where it stopped
+    let tarball_name = url_file_name(url)?;
+    let download_path = cache.partial_download_path(url)?;
+
+    println!("Downloading {} ... ", tarball_name);
+    download(url, &download_path)?;
+    if let Err(e) = verify_sha256(&download_path, url, sha256) {
+        remove_path(&download_path)?;
+        return Err(e);
+    }
+
+    cache.insert(Some(url), &tarball_name, &download_path, true)
+}
+
+/// Make sure the file at `path`, which came from `origin`, has the expected SHA-256,
+/// or show what it is when nothing is expected so it can be pinned in the config
+fn verify_sha256(path: &Path, origin: &str, expected: Option<&str>) -> Result<()> {
+    let actual = sha256_file(path)?;
+
+    match expected {
+        Some(expected) if !actual.eq_ignore_ascii_case(expected) => bail!(
+            "{} has SHA-256 {}, but [wine.build] sha256 is {}",
+            origin,
+            actual,
+            expected
+        ),
+        Some(_) => {}
+        None => println!(
+            r#"Add sha256 = "{}" to [wine.build] to make sure later builds use the same wine"#,
+            actual
+        ),
+    }
+
+    Ok(())
+}
+
+/// Extract the wine tarball at {wine_archive_path} to {wine_dir}
+fn extract_wine(
+    log: &BuildLog,
+    source: &WineSource,
+    build_path: &Path,
+    wine_archive_path: &Path,
+    wine_dir: &Path,
+) -> Result<()> {
+    // Extract to a directory of its own, and only move wine into place once it's complete
+    let extract_path = build_path.join("extract");
+    remove_path(&extract_path)?;
+    fs::create_dir(&extract_path).with_context(|| "Creating directory to extract wine into")?;
+
+    // Every entry goes into the log, and a count of them to the terminal
+    let mut log_file = log.open("wine")?;
+    let mut entry_count = 0;
+    archive::unpack(wine_archive_path, &extract_path, |path| {
+        entry_count += 1;
+        writeln!(log_file, "{}", path.display())?;
+        print!("\rExtracted {} files", entry_count);
+        io::stdout().flush()?;
+        Ok(())
+    })
+    .with_context(|| format!("Extracting {}", wine_archive_path.display()))?;
+    println!();
+
+    // Every kind of build keeps wine somewhere else in the archive, move it to the wine
+    // directory instead
+    let wine_root = find_wine_root(source, &extract_path)?;
+    remove_path(wine_dir)?;
+    fs::rename(&wine_root, wine_dir).with_context(|| {
+        format!(
+            "Moving wine from {} to {}",
+            wine_root.display(),
+            wine_dir.display()
+        )
+    })?;
+    remove_path(&extract_path)?;
+
+    Ok(())
+}
+
+/// Find the directory with `bin/wine` in it, where builds from `source` keep it
+fn find_wine_root(source: &WineSource, dir: &Path) -> Result<PathBuf> {
+    let wine_root = match source {
+        // Portable builds from winehq have everything in usr/
+        WineSource::Winehq => Some(dir.join("usr")).filter(|root| has_wine(root)),
+
+        // Gcenx builds are app bundles like Wine Stable.app or Wine Crossover.app, with
+        // wine in Contents/Resources/wine
+        WineSource::Gcenx => find_app_bundle_wine(dir)?,
+
+        // Anything else could be laid out any way at all
+        _ => search_wine_root(dir, SEARCH_DEPTH)?,
+    };
+
+    wine_root.ok_or_else(|| anyhow!("Can't find bin/wine or bin/wine64 in {}", dir.display()))
+}
+
+fn find_app_bundle_wine(dir: &Path) -> Result<Option<PathBuf>> {
+    for entry in sorted_entries(dir)? {
+        let wine_root = entry.join("Contents/Resources/wine");
+        if entry
+            .extension()
+            .is_some_and(|extension| extension == "app")
+            && has_wine(&wine_root)
+        {
+            return Ok(Some(wine_root));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Look for wine in `dir` and the directories below it, the shallowest one first
+fn search_wine_root(dir: &Path, depth: usize) -> Result<Option<PathBuf>> {
+    let mut level = vec![dir.to_path_buf()];
+
+    for _ in 0..=depth {
+        if let Some(wine_root) = level.iter().find(|dir| has_wine(dir)) {
+            return Ok(Some(wine_root.clone()));
+        }
+
+        let mut next_level = Vec::new();
+        for dir in &level {
+            next_level.extend(
+                sorted_entries(dir)?
+                    .into_iter()
+                    .filter(|entry| entry.symlink_metadata().is_ok_and(|m| m.is_dir())),
+            );
+        }
+        level = next_level;
+    }
+
+    Ok(None)
+}
+
+fn has_wine(dir: &Path) -> bool {
+    dir.join("bin/wine").is_file() || dir.join("bin/wine64").is_file()
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Reading {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Reading {}", dir.display()))?;
+    entries.sort();
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::tests::{ok, serve};
+    use tempfile::TempDir;
+
+    #[test]
+    fn refuses_downloads_with_the_wrong_hash() {
+        let (url, server) = serve(vec![ok(b"not the wine you're looking for")]);
+        let dir = TempDir::new().unwrap();
+        let mut cache = DownloadCache::open_in(dir.path().to_path_buf()).unwrap();
+
+        let error = fetch_wine(&mut cache, &url, Some(&"0".repeat(64)), false).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("has SHA-256"), "{:#}", error);
+        assert!(!cache.partial_download_path(&url).unwrap().exists());
+        assert!(cache.lookup(&url, None).unwrap().is_none());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Context, Error, Result};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// How many times a download is tried before giving up
+const ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry, doubled after every failed attempt
+#[cfg(not(test))]
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+#[cfg(test)]
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+const CONNECT_TIMEOUT_MILLIS: u64 = 30_000;
+const READ_TIMEOUT_MILLIS: u64 = 60_000;
+
+/// Why a download attempt failed
+enum Failure {
+    /// Trying again might work, like after a dropped connection or a server error
+    Temporary(Error),
+
+    /// Trying again won't help, like after a 404
+    Permanent(Error),
+}
+
+/// Stream `url` into the file at `path`. Whatever an interrupted download left at
+/// `path` is resumed with a range request, and failed attempts are retried with a
+/// growing delay in between.
+pub fn download(url: &str, path: &Path) -> Result<()> {
+    let mut attempt = 1;
+    let mut delay = FIRST_RETRY_DELAY;
+
+    loop {
+        match download_once(url, path) {
+            Ok(()) => return Ok(()),
+            Err(Failure::Temporary(e)) if attempt < ATTEMPTS => {
+                println!(
+                    "Downloading {} failed: {:#}, retrying in {} seconds ...",
+                    url,
+                    e,
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+                attempt += 1;
+                delay *= 2;
+            }
+            Err(Failure::Temporary(e)) => {
+                return Err(e.context(format!("Downloading {} failed {} times", url, ATTEMPTS)))
+            }
+            Err(Failure::Permanent(e)) => return Err(e.context(format!("Downloading {}", url))),
+        }
+    }
+}
+
+fn download_once(url: &str, path: &Path) -> Result<(), Failure> {
+    let downloaded = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+
+    let mut request = ureq::get(url);
+    request
+        .timeout_connect(CONNECT_TIMEOUT_MILLIS)
+        .timeout_read(READ_TIMEOUT_MILLIS);
+    if downloaded > 0 {
+        request.set("Range", &format!("bytes={}-", downloaded));
+    }
+
+    let response = request.call();
+    if let Some(e) = response.synthetic_error() {
+        return Err(Failure::Temporary(anyhow!("{}", e)));
+    }
+
+    // Servers that don't do ranges send the whole file again
+    let resuming = match response.status() {
+        206 => {
+            let expected_range = format!("bytes {}-", downloaded);
+            match response.header("Content-Range") {
+                Some(range) if range.starts_with(&expected_range) => true,
+                _ => {
+                    remove_partial(path)?;
+                    return Err(Failure::Temporary(anyhow!(
+                        "The server sent the wrong part of the file"
+                    )));
+                }
+            }
+        }
+        200..=299 => false,
+        416 => {
+            remove_partial(path)?;
+            return Err(Failure::Temporary(anyhow!(
+                "The server can't resume the download"
+            )));
+        }
+        status => {
+            let e = anyhow!("The server responded {}", response.status_line());
+            return Err(match status {
+                408 | 429 | 500..=599 => Failure::Temporary(e),
+                _ => Failure::Permanent(e),
+            });
+        }
+    };
+
+    // Without a Content-Length there's no telling whether the download is complete
+    let expected_len = response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .map(|len| if resuming { downloaded + len } else { len });
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resuming)
+        .truncate(!resuming)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))
+        .map_err(Failure::Permanent)?;
+
+    // Keep what was received so far when the connection drops, the next attempt resumes it
+    io::copy(&mut response.into_reader(), &mut file).map_err(|e| Failure::Temporary(e.into()))?;
+
+    let len = file
+        .metadata()
+        .with_context(|| format!("Reading {}", path.display()))
+        .map_err(Failure::Permanent)?
+        .len();
+    match expected_len {
+        Some(expected_len) if len < expected_len => Err(Failure::Temporary(anyhow!(
+            "The connection closed after {} of {} bytes",
+            len,
+            expected_len
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn remove_partial(path: &Path) -> Result<(), Failure> {
+    if path.exists() {
+        fs::remove_file(path)
+            .with_context(|| format!("Removing {}", path.display()))
+            .map_err(Failure::Permanent)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+    use tempfile::TempDir;
+
+    const CONTENTS: &[u8] = b"wine, or at least something that stands in for it";
+
+    /// A server on localhost that answers each connection it gets with the next of
+    /// `responses`, and hands back the requests it got once it answered them all
+    pub fn serve(responses: Vec<Vec<u8>>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/wine.tar.gz", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = String::new();
+                    let mut reader = BufReader::new(&mut stream);
+                    while reader.read_line(&mut request).unwrap() > 2 {}
+
+                    stream.write_all(&response).unwrap();
+                    request
+                })
+                .collect()
+        });
+
+        (url, server)
+    }
+
+    /// An HTTP response with `body`, which doesn't have to be as long as its
+    /// Content-Length says
+    pub fn response(status: &str, headers: &[(&str, String)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for (name, value) in headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    pub fn ok(body: &[u8]) -> Vec<u8> {
+        response("200 OK", &[("Content-Length", body.len().to_string())], body)
+    }
+
+    fn range_header(request: &str) -> Option<&str> {
+        request
+            .lines()
+            .find_map(|line| line.strip_prefix("Range: "))
+            .map(str::trim)
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", &[("Content-Length", "0".to_string())], b""),
+            ok(CONTENTS),
+        ]);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("wine.tar.gz.part");
+
+        download(&url, &path).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENTS);
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resumes_dropped_connections() {
+        let split = 20;
+        let (url, server) = serve(vec![
+            response(
+                "200 OK",
+                &[("Content-Length", CONTENTS.len().to_string())],
+                &CONTENTS[..split],
+            ),
+            response(
+                "206 Partial Content",
+                &[
+                    ("Content-Length", (CONTENTS.len() - split).to_string()),
+                    (
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", split, CONTENTS.len() - 1, CONTENTS.len()),
+                    ),
+                ],
+                &CONTENTS[split..],
+            ),
+        ]);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("wine.tar.gz.part");
+
+        download(&url, &path).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENTS);
+        let requests = server.join().unwrap();
+        assert_eq!(range_header(&requests[0]), None);
+        assert_eq!(range_header(&requests[1]), Some("bytes=20-"));
+    }
+
+    #[test]
+    fn starts_over_when_ranges_are_ignored() {
+        let (url, server) = serve(vec![ok(CONTENTS)]);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("wine.tar.gz.part");
+        fs::write(&path, b"part of something else").unwrap();
+
+        download(&url, &path).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENTS);
+        let requests = server.join().unwrap();
+        assert_eq!(range_header(&requests[0]), Some("bytes=22-"));
+    }
+
+    #[test]
+    fn gives_up_on_missing_files() {
+        let (url, server) = serve(vec![response(
+            "404 Not Found",
+            &[("Content-Length", "0".to_string())],
+            b"",
+        )]);
+        let dir = TempDir::new().unwrap();
+
+        let error = download(&url, &dir.path().join("wine.tar.gz.part")).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("404"), "{:#}", error);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+}
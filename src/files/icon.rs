@@ -0,0 +1,316 @@
+use anyhow::{anyhow, bail, Context, Result};
+use image::imageops::{self, FilterType};
+use image::{ImageOutputFormat, RgbaImage};
+use pelite::PeFile;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Name of the icon file in Contents/Resources
+pub const ICON_FILE_NAME: &str = "AppIcon.icns";
+
+const ICNS_MAGIC: &[u8] = b"icns";
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const ICO_MAGIC: &[u8] = b"\x00\x00\x01\x00";
+
+/// The ICNS element types that hold PNG data, with their size in pixels. This is the
+/// same set iconutil writes for a full iconset, 16x16 through 512x512@2x.
+const ICNS_ELEMENTS: &[(&[u8; 4], u32)] = &[
+    (b"icp4", 16),
+    (b"ic11", 32), // 16x16@2x
+    (b"icp5", 32),
+    (b"ic12", 64), // 32x32@2x
+    (b"ic07", 128),
+    (b"ic13", 256), // 128x128@2x
+    (b"ic08", 256),
+    (b"ic14", 512), // 256x256@2x
+    (b"ic09", 512),
+    (b"ic10", 1024), // 512x512@2x
+];
+
+/// Turn the PNG, ICO or ICNS file at `icon_path` into Contents/Resources/AppIcon.icns
+pub fn create_icon(icon_path: &Path, app_path: &Path) -> Result<()> {
+    let icon =
+        fs::read(icon_path).with_context(|| format!("Reading icon {}", icon_path.display()))?;
+    let icns = to_icns(icon).with_context(|| format!("Converting icon {}", icon_path.display()))?;
+
+    write_icns(&icns, app_path)
+}
+
+/// Use the largest icon in the resources of the Windows executable at `exe_path` as
+/// Contents/Resources/AppIcon.icns
+pub fn create_icon_from_exe(exe_path: &Path, app_path: &Path) -> Result<()> {
+    let exe = fs::read(exe_path).with_context(|| format!("Reading {}", exe_path.display()))?;
+    let ico = extract_exe_icon(&exe)
+        .with_context(|| format!("Extracting icon from {}", exe_path.display()))?;
+    let icns =
+        to_icns(ico).with_context(|| format!("Converting icon from {}", exe_path.display()))?;
+
+    write_icns(&icns, app_path)
+}
+
+fn write_icns(icns: &[u8], app_path: &Path) -> Result<()> {
+    let icns_path = app_path.join("Contents/Resources").join(ICON_FILE_NAME);
+    fs::write(&icns_path, icns).with_context(|| format!("Writing {}", icns_path.display()))
+}
+
+/// Convert PNG or ICO data to ICNS, ICNS data is passed through as is
+fn to_icns(icon: Vec<u8>) -> Result<Vec<u8>> {
+    if icon.starts_with(ICNS_MAGIC) {
+        Ok(icon)
+    } else if icon.starts_with(PNG_MAGIC) || icon.starts_with(ICO_MAGIC) {
+        // For ICO files this decodes the largest image in the file
+        let image = image::load_from_memory(&icon)?.to_rgba8();
+        encode_icns(&image)
+    } else {
+        bail!("Not a PNG, ICO or ICNS file");
+    }
+}
+
+/// Find the RT_GROUP_ICON resource holding the largest icon in a PE file, and return it
+/// as the contents of an ICO file
+fn extract_exe_icon(exe: &[u8]) -> Result<Vec<u8>> {
+    let pe = PeFile::from_bytes(exe)?;
+    let resources = pe.resources()?;
+
+    let mut largest = None;
+    for icon in resources.icons() {
+        let (_, group) = icon?;
+
+        // Widths of 0 mean 256 pixels
+        let size = group
+            .entries()
+            .iter()
+            .map(|entry| match entry.bWidth {
+                0 => (256, entry.wBitCount),
+                width => (width as u32, entry.wBitCount),
+            })
+            .max();
+
+        match (&largest, size) {
+            (Some((largest_size, _)), Some(size)) if *largest_size >= size => {}
+            (_, Some(size)) => largest = Some((size, group)),
+            (_, None) => {}
+        }
+    }
+
+    let (_, group) = largest.ok_or_else(|| anyhow!("No icon resources found"))?;
+    let mut ico = Vec::new();
+    group.write(&mut ico)?;
+
+    Ok(ico)
+}
+
+/// Build an ICNS file with every element in `ICNS_ELEMENTS`, scaled from `image`
+pub fn encode_icns(image: &RgbaImage) -> Result<Vec<u8>> {
+    let image = &square(image);
+    let mut elements = Vec::new();
+
+    for (element_type, size) in ICNS_ELEMENTS {
+        let scaled = imageops::resize(image, *size, *size, FilterType::Lanczos3);
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(scaled)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .with_context(|| format!("Encoding {}x{} icon", size, size))?;
+
+        // Every element is its type, its length including this 8 byte header, then data
+        elements.extend_from_slice(*element_type);
+        elements.extend_from_slice(&(png.len() as u32 + 8).to_be_bytes());
+        elements.extend_from_slice(&png);
+    }
+
+    let mut icns = Vec::with_capacity(elements.len() + 8);
+    icns.extend_from_slice(ICNS_MAGIC);
+    icns.extend_from_slice(&(elements.len() as u32 + 8).to_be_bytes());
+    icns.extend_from_slice(&elements);
+
+    Ok(icns)
+}
+
+/// `image` in the middle of a transparent square, so scaling it to the square icon sizes
+/// doesn't stretch it
+fn square(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let side = width.max(height);
+
+    let mut square = RgbaImage::new(side, side);
+    imageops::replace(
+        &mut square,
+        image,
+        ((side - width) / 2).into(),
+        ((side - height) / 2).into(),
+    );
+
+    square
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba};
+    use std::convert::TryInto;
+
+    /// An opaque red image as PNG
+    fn red_png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        png
+    }
+
+    fn be_u32(bytes: &[u8]) -> usize {
+        u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize
+    }
+
+    /// Check the structure of `icns` and return the PNG of its first element
+    fn check_icns(icns: &[u8]) -> &[u8] {
+        assert_eq!(&icns[..4], ICNS_MAGIC);
+        assert_eq!(be_u32(&icns[4..]), icns.len());
+
+        let mut offset = 8;
+        let mut first = None;
+        for (element_type, size) in ICNS_ELEMENTS {
+            assert_eq!(&icns[offset..offset + 4], &element_type[..]);
+            let length = be_u32(&icns[offset + 4..]);
+            let png = &icns[offset + 8..offset + length];
+            assert!(png.starts_with(PNG_MAGIC));
+            let element = image::load_from_memory(png).unwrap();
+            assert_eq!(element.dimensions(), (*size, *size));
+
+            first.get_or_insert(png);
+            offset += length;
+        }
+        assert_eq!(offset, icns.len());
+
+        first.unwrap()
+    }
+
+    #[test]
+    fn pads_images_that_arent_square() {
+        let icns = to_icns(red_png(64, 32)).unwrap();
+        let icon = image::load_from_memory(check_icns(&icns))
+            .unwrap()
+            .to_rgba8();
+
+        // 16x16, with the 16x8 image in the middle
+        assert_eq!(icon.get_pixel(8, 0)[3], 0);
+        assert_eq!(icon.get_pixel(8, 15)[3], 0);
+        assert_eq!(*icon.get_pixel(8, 8), Rgba([255, 0, 0, 255]));
+        assert_eq!(*icon.get_pixel(0, 8), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn extracts_icons_from_executables() {
+        let exe = exe_with_icon(&red_png(32, 32));
+        let ico = extract_exe_icon(&exe).unwrap();
+        assert!(ico.starts_with(ICO_MAGIC));
+
+        check_icns(&to_icns(ico).unwrap());
+    }
+
+    /// A 32-bit PE file with nothing in it but a resource section with one icon, group
+    /// 1 with icon 1, which is `png`
+    fn exe_with_icon(png: &[u8]) -> Vec<u8> {
+        const SECTION_RVA: u32 = 0x1000;
+        const HEADERS_SIZE: usize = 0x200;
+
+        fn u16s(out: &mut Vec<u8>, values: &[u16]) {
+            values
+                .iter()
+                .for_each(|value| out.extend(value.to_le_bytes()));
+        }
+        fn u32s(out: &mut Vec<u8>, values: &[u32]) {
+            values
+                .iter()
+                .for_each(|value| out.extend(value.to_le_bytes()));
+        }
+        // A resource directory with entries of an ID and the offset of what it points at
+        fn directory(out: &mut Vec<u8>, entries: &[(u32, u32)]) {
+            u32s(out, &[0, 0]);
+            u16s(out, &[0, 0, 0, entries.len() as u16]);
+            for (id, offset) in entries {
+                u32s(out, &[*id, *offset]);
+            }
+        }
+        const SUBDIRECTORY: u32 = 0x8000_0000;
+        const RT_ICON: u32 = 3;
+        const RT_GROUP_ICON: u32 = 14;
+        const LANGUAGE: u32 = 0x409;
+
+        // Directories: types at 0, names at 32 and 56, languages at 80 and 104, then
+        // the data entries at 128 and 144, the group at 160 and the icon at 184
+        let mut rsrc = Vec::new();
+        directory(
+            &mut rsrc,
+            &[
+                (RT_ICON, SUBDIRECTORY | 32),
+                (RT_GROUP_ICON, SUBDIRECTORY | 56),
+            ],
+        );
+        directory(&mut rsrc, &[(1, SUBDIRECTORY | 80)]);
+        directory(&mut rsrc, &[(1, SUBDIRECTORY | 104)]);
+        directory(&mut rsrc, &[(LANGUAGE, 128)]);
+        directory(&mut rsrc, &[(LANGUAGE, 144)]);
+        let group_size = 6 + 14;
+        u32s(&mut rsrc, &[SECTION_RVA + 184, png.len() as u32, 0, 0]);
+        u32s(&mut rsrc, &[SECTION_RVA + 160, group_size, 0, 0]);
+        // The group: reserved, type 1 for icons, one entry of a 32x32 32-bit icon
+        u16s(&mut rsrc, &[0, 1, 1]);
+        rsrc.extend([32, 32, 0, 0]);
+        u16s(&mut rsrc, &[1, 32]);
+        u32s(&mut rsrc, &[png.len() as u32]);
+        u16s(&mut rsrc, &[1]);
+        rsrc.resize(184, 0);
+        rsrc.extend(png);
+        let raw_size = (rsrc.len() + 0x1ff) & !0x1ff;
+        let virtual_size = (rsrc.len() as u32 + 0xfff) & !0xfff;
+
+        let mut exe = vec![0; 0x40];
+        exe[..2].copy_from_slice(b"MZ");
+        exe[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        exe.extend(b"PE\0\0");
+        // COFF header: i386, one section, a 224 byte optional header, an executable
+        u16s(&mut exe, &[0x14c, 1]);
+        u32s(&mut exe, &[0, 0, 0]);
+        u16s(&mut exe, &[224, 0x0102]);
+        // Optional header, with only the resource directory
+        u16s(&mut exe, &[0x10b, 0]);
+        u32s(
+            &mut exe,
+            &[0, raw_size as u32, 0, 0, 0, 0, 0x400000, 0x1000, 0x200],
+        );
+        u16s(&mut exe, &[4, 0, 0, 0, 4, 0]);
+        u32s(
+            &mut exe,
+            &[0, SECTION_RVA + virtual_size, HEADERS_SIZE as u32, 0],
+        );
+        u16s(&mut exe, &[2, 0]);
+        u32s(&mut exe, &[0x100000, 0x1000, 0x100000, 0x1000, 0, 16]);
+        for directory in 0..16 {
+            match directory {
+                2 => u32s(&mut exe, &[SECTION_RVA, rsrc.len() as u32]),
+                _ => u32s(&mut exe, &[0, 0]),
+            }
+        }
+        // The section header
+        exe.extend(b".rsrc\0\0\0");
+        u32s(
+            &mut exe,
+            &[
+                rsrc.len() as u32,
+                SECTION_RVA,
+                raw_size as u32,
+                HEADERS_SIZE as u32,
+            ],
+        );
+        u32s(&mut exe, &[0, 0, 0, 0x4000_0040]);
+
+        exe.resize(HEADERS_SIZE, 0);
+        rsrc.resize(raw_size, 0);
+        exe.extend(rsrc);
+        exe
+    }
+}
@@ -1,37 +1,102 @@
-use crate::config;
+use crate::config::{self, PlistFormat};
 use anyhow::{Context, Result};
-use std::fs::File;
-use std::io::prelude::*;
+use serde::Serialize;
+use std::fs;
 use std::path::Path;
 
-// We can create a macro so we don't have to put this string literal in the middle of our code
-macro_rules! format_info_plist {
-    ( $($e:expr),* ) => {
-    format!(r###"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-  <key>CFBundleExecutable</key>
-  <string>{}</string>
-  <key>CFBundleIconFile</key>
-  <string>{}</string>
-</dict>
-</plist>"###, $($e,)+);
+/// Package type and creator code, written to both Info.plist and PkgInfo
+const PACKAGE_TYPE: &str = "APPL";
+const SIGNATURE: &str = "????";
+
+/// The keys boxwine sets in Contents/Info.plist
+#[derive(Serialize)]
+struct InfoPlist {
+    #[serde(rename = "CFBundleInfoDictionaryVersion")]
+    info_dictionary_version: &'static str,
+
+    #[serde(rename = "CFBundlePackageType")]
+    package_type: &'static str,
+
+    #[serde(rename = "CFBundleSignature")]
+    signature: &'static str,
+
+    #[serde(rename = "CFBundleExecutable")]
+    executable: String,
+
+    #[serde(rename = "CFBundleIdentifier")]
+    identifier: String,
+
+    #[serde(rename = "CFBundleName")]
+    name: String,
+
+    #[serde(rename = "CFBundleDisplayName")]
+    display_name: String,
+
+    #[serde(rename = "CFBundleShortVersionString")]
+    short_version: String,
+
+    #[serde(rename = "CFBundleVersion")]
+    version: String,
+
+    #[serde(rename = "CFBundleIconFile", skip_serializing_if = "Option::is_none")]
+    icon_file: Option<String>,
+
+    #[serde(rename = "LSMinimumSystemVersion")]
+    minimum_system_version: String,
+
+    #[serde(
+        rename = "LSApplicationCategoryType",
+        skip_serializing_if = "Option::is_none"
+    )]
+    category: Option<String>,
+
+    #[serde(rename = "NSHighResolutionCapable")]
+    high_resolution: bool,
+}
+
+impl InfoPlist {
+    fn from_config(
+        config: &config::Config,
+        executable: &str,
+        icon_file: Option<&str>,
+    ) -> InfoPlist {
+        InfoPlist {
+            info_dictionary_version: "6.0",
+            package_type: PACKAGE_TYPE,
+            signature: SIGNATURE,
+            executable: executable.to_string(),
+            identifier: config.get_bundle_identifier(),
+            name: config.get_app_name().clone(),
+            display_name: config.get_display_name().clone(),
+            short_version: config.get_version().to_string(),
+            version: config.get_build_version().to_string(),
+            icon_file: icon_file.map(str::to_string),
+            minimum_system_version: config.get_minimum_system_version().to_string(),
+            category: config.get_app_category().clone(),
+            high_resolution: config.get_high_resolution(),
+        }
     }
 }
 
-pub fn create_info_plist(config: &config::Config, app_path: &Path) -> Result<()> {
-    let info_plist_path = app_path.join("Info.plist");
+/// Write Contents/Info.plist and Contents/PkgInfo
+pub fn create_info_plist(
+    config: &config::Config,
+    executable: &str,
+    icon_file: Option<&str>,
+    app_path: &Path,
+) -> Result<()> {
+    let info_plist_path = app_path.join("Contents/Info.plist");
+    let info_plist = InfoPlist::from_config(config, executable, icon_file);
 
-    let mut app_icon_path = &"".to_string();
-    if config.get_app_icon().is_some() {
-        app_icon_path = config.get_app_icon().as_ref().unwrap();
+    match config.get_plist_format() {
+        PlistFormat::Xml => plist::to_file_xml(&info_plist_path, &info_plist),
+        PlistFormat::Binary => plist::to_file_binary(&info_plist_path, &info_plist),
     }
+    .with_context(|| "Writing Info.plist")?;
 
-    let info_plist = format_info_plist!("launch", app_icon_path);
+    let pkg_info_path = app_path.join("Contents/PkgInfo");
+    fs::write(pkg_info_path, format!("{}{}", PACKAGE_TYPE, SIGNATURE))
+        .with_context(|| "Writing PkgInfo")?;
 
-    let mut file = File::create(&info_plist_path).with_context(|| "Creating Info.plist")?;
-    file.write_all(info_plist.as_bytes())
-        .with_context(|| "Writing Info.plist")?;
     Ok(())
 }
@@ -1,49 +1,46 @@
-use crate::config::Config;
-use anyhow::{Context, Result};
-use std::fs::File;
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fs;
 use std::fs::Permissions;
-use std::io::prelude::*;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-
-// We can create a macro so we don't have to put this string literal in the middle of our code
-macro_rules! format_launch_script {
-    ( $($e:expr),* ) => {
-    format!(r###"#!/bin/sh
-
-# Get directory of this executable, shamelessly taken from https://stackoverflow.com/a/246128
-DIR="$( cd "$( dirname "${{BASH_SOURCE[0]}}" )" >/dev/null 2>&1 && pwd )"
-
-
-WINEPREFIX="${{DIR}}/{}"
-
-if test -f "${{WINEPREFIX}}.tar.gz"; then
-    echo "Uncompressing wineprefix ..."
-    tar -xzf "${{WINEPREFIX}}.tar.gz" -C "${{WINEPREFIX}}"
-    rm "${{WINEPREFIX}}.tar.gz"
-    echo "Done!"
-fi
-
-WINE="${{DIR}}/wine/bin/wine"
-
-# Launch wine
-WINEPREFIX="${{WINEPREFIX}}" "${{WINE}}" start 'C:/windows/system32/cmd.exe'
-"###, $($e,)+);
-    }
-}
-
-pub fn create_launch(config: &Config, wineprefix_name: &str, app_path: &Path) -> Result<()> {
-    let launch_script_path = app_path.join("Contents/MacOS/launch");
-
-    let launch_script = format_launch_script!(wineprefix_name);
-
-    let mut file = File::create(&launch_script_path).with_context(|| "Creating Info.plist")?;
-    file.write_all(launch_script.as_bytes())
-        .with_context(|| "Writing Info.plist")?;
+use std::path::{Path, PathBuf};
+
+/// Name of the launcher binary that is built alongside boxwine
+const LAUNCHER_NAME: &str = "boxwine-launcher";
+
+/// Copy the launcher into the app. Unless a specific launcher binary is given,
+/// the one that was built next to this boxwine executable is used.
+pub fn create_launch(
+    launcher_path: &Option<String>,
+    executable: &str,
+    app_path: &Path,
+) -> Result<()> {
+    let launch_path = app_path.join("Contents/MacOS").join(executable);
+
+    let launcher_path = match launcher_path {
+        Some(path) => PathBuf::from(path),
+        None => default_launcher_path()?,
+    };
+
+    fs::copy(&launcher_path, &launch_path).with_context(|| {
+        format!(
+            "Copying launcher from {} (use --launcher to pick a different one)",
+            launcher_path.display()
+        )
+    })?;
 
     // Make it executable
-    file.set_permissions(Permissions::from_mode(0o755))
+    fs::set_permissions(&launch_path, Permissions::from_mode(0o755))
         .with_context(|| "Setting exec permission on launch file")?;
 
     Ok(())
 }
+
+fn default_launcher_path() -> Result<PathBuf> {
+    let boxwine_path = env::current_exe().with_context(|| "Locating the boxwine executable")?;
+    let launcher_dir = boxwine_path
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no parent directory", boxwine_path.display()))?;
+
+    Ok(launcher_dir.join(LAUNCHER_NAME))
+}
@@ -0,0 +1,52 @@
+use crate::config::Config;
+use anyhow::Result;
+use boxwine::manifest::{Entrypoint, Manifest};
+use boxwine::winpath::WindowsPath;
+use std::path::Path;
+
+pub fn create_manifest(
+    config: &Config,
+    wine_dir_name: &str,
+    wineprefix_name: &str,
+    wineprefix_version: &str,
+    app_path: &Path,
+) -> Result<()> {
+    let entrypoint = config.get_entrypoint();
+
+    let compression = config.get_wineprefix_compression();
+    let wineprefix_archive = compression
+        .map(|compression| format!("{}.{}", wineprefix_name, compression.format.extension()));
+
+    let manifest = Manifest {
+        name: config.get_app_name().clone(),
+        identifier: config.get_bundle_identifier(),
+        wine_dir: wine_dir_name.to_string(),
+        wineprefix: wineprefix_name.to_string(),
+        wineprefix_location: config.get_wineprefix_location(),
+        wineprefix_version: Some(wineprefix_version.to_string()),
+        wineprefix_upgrade: config.get_wineprefix_upgrade(),
+        wineprefix_archive,
+        wineprefix_compression: compression.map(|compression| compression.format),
+        dll_overrides: config.get_wine_dll_overrides(),
+        persist: config.get_persist(),
+        entrypoint: Entrypoint {
+            program: to_wine_path(&entrypoint.program)?,
+            args: entrypoint.args.clone().unwrap_or_default(),
+            env: entrypoint.env.clone().unwrap_or_default(),
+            cwd: entrypoint.cwd.as_deref().map(to_wine_path).transpose()?,
+        },
+    };
+
+    manifest.write(app_path)
+}
+
+/// Turn a Windows path like `c:/Program Files/app.exe` into `C:\Program Files\app.exe`,
+/// which is the form wine expects. Anything that doesn't start with a drive letter is
+/// passed through untouched.
+fn to_wine_path(path: &str) -> Result<String> {
+    if WindowsPath::is_windows_path(path) {
+        Ok(WindowsPath::parse(path)?.to_string())
+    } else {
+        Ok(path.to_string())
+    }
+}
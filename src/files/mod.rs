@@ -1,2 +1,4 @@
+pub mod icon;
 pub mod info_plist;
 pub mod launch;
+pub mod manifest;
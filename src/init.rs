@@ -1,11 +1,11 @@
 use anyhow::Result;
-use clap::Clap;
+use clap::Parser;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
 /// Initialize an example config file
-#[derive(Clap)]
+#[derive(Parser)]
 pub struct Init {
     /// Path to where you want the example config file
     #[clap(short, long, default_value = "app.boxwine.toml")]
@@ -14,7 +14,7 @@ pub struct Init {
 
 pub fn init(opts: Init) -> Result<()> {
     let path = Path::new(&opts.file);
-    let mut file = File::create(&path)?;
+    let mut file = File::create(path)?;
 
     // Write the `EXAMPLE_CONFIG` string to `file`, returns `io::Result<()>`
     file.write_all(EXAMPLE_CONFIG.as_bytes())?;
@@ -32,13 +32,65 @@ static EXAMPLE_CONFIG: &str = r###"# This is an example configuration file that
 # "wineprefix" refers to the virtual windows installation where all
 # of the windows files are stored. Think of it as C:/
 
+# a config can build on others, so similar apps can share their wine build, verbs and
+# wineprefix settings. The config it extends comes first, then the ones it includes in
+# order, then this one, and what comes later overrides what came before. Tables are
+# merged key by key, except for [wine.source] and a compress_wineprefix table, which
+# are replaced as a whole. [[wine.volume]], [[wine.run]] and [[app.persist]] entries
+# are added to the ones from before. Anything else, like the list of verbs, is
+# replaced. Paths are relative to this file.
+# `boxwine config show --resolved` shows where each value came from. Default empty.
+#
+# extends = "base.boxwine.toml"
+# include = ["winetricks.boxwine.toml"]
+
+# paths on the host that aren't absolute are relative to the config file they're in,
+# not to where boxwine runs.
+#
+# any value can use variables, written ${NAME}. $${ is a literal ${.
+# ${WINEPREFIX} is where the wineprefix is on the host and ${DRIVE_C} its drive C:,
+# in [app.entrypoint] they're where they are on the Mac the app runs on.
+# ${CONFIG_DIR} is the folder of the config file the value is in, ${APP_NAME} the
+# name of the app and ${env:NAME} the environment variable NAME where boxwine runs.
+# Your own variables go in [vars] and can use other variables too. Default empty.
+#
+# [vars]
+# installers = "${CONFIG_DIR}/installers"
+# saves = "${DRIVE_C}/users/Public/${APP_NAME}"
+
 [app]
 # name of your app, default "My App"
 name = "My App"
 
-# path to the icon to be used, default empty    
+# path to the icon to be used. PNG and ICO files are converted to a
+# multi-resolution ICNS, ICNS files are used as they are. Leave it out or set it
+# to "auto" to use the icon of the entrypoint program. Default empty.
 icon = "path/to/icon.png"
 
+# unique identifier of the app, default "com.boxwine." followed by the name
+identifier = "com.example.my-app"
+
+# name shown in the Finder and the Dock, default is the same as name
+display_name = "My App"
+
+# user-visible version of the app, default "1.0"
+version = "1.0"
+
+# build number of the app, default is the same as version
+build = "1"
+
+# oldest macOS version the app runs on, default "10.13"
+minimum_system_version = "10.13"
+
+# whether the app can render at Retina resolutions, default true
+high_resolution = true
+
+# App Store category of the app, default empty
+category = "public.app-category.games"
+
+# write Info.plist as "xml" or "binary", default "xml"
+plist_format = "xml"
+
 [app.entrypoint]
 # the path of the program to start when you run the app, in the wine prefix, required.
 program = "C:/to/run/in/wine/program.exe"
@@ -46,9 +98,35 @@ program = "C:/to/run/in/wine/program.exe"
 # any additional arguments you want to pass to 
 args = ["--some-arg true", "--another-one"]
 
+# user data that should survive anything that happens to the wineprefix, like upgrades
+# to a new build of the app. Paths are on drive C:, <user> is the name of the user
+# running the app. The launcher keeps them in a directory of their own next to the
+# wineprefix and links them into it. Registry keys are saved there when the app quits
+# and put back when it starts. Default empty.
+#
+# [[app.persist]]
+# path = "c:/users/<user>/Saved Games/My App"
+#
+# [[app.persist]]
+# registry = 'HKEY_CURRENT_USER\Software\My App'
 
 [wine]
  
+# where to get wine from, default "winehq"
+#
+# "winehq" downloads a portable build from dl.winehq.org.
+# "gcenx" downloads a build from github.com/Gcenx/macOS_Wine_builds. Use
+# branch "crossover" in [wine.build] for the CrossOver-derived winecx builds.
+# "url" downloads a tarball from url, where {branch}, {version} and {arch}
+# are filled in from [wine.build].
+# "tarball" unpacks a tarball on the host at path.
+# "directory" uses wine that's already unpacked on the host at path.
+#
+[wine.source]
+type = "winehq"
+# url = "https://example.com/wine-{branch}-{version}-osx{arch}.tar.xz"
+# path = "path/to/wine/on/host"
+
 # if you want to choose a specific build and version of wine
 [wine.build]
 # default "stable"
@@ -60,9 +138,15 @@ version = "5.0"
 # default "64"
 arch = "64"
 
+# SHA-256 of the wine tarball. The build fails if the download doesn't match it.
+# Without it, boxwine prints the hash of whatever it downloaded. Default empty.
+#
+# sha256 = "0123456789abcdef..."
+
 [wine.prefix]
-# you can also specify the windows architecture, default win64.
-# If you use base_prefix, this will be ignored.
+# the windows architecture of the wineprefix: "win32", "win64" or "wow64", default
+# "win64". A 32-bit wine build (arch = "32") can only make "win32" wineprefixes. If you
+# use base_prefix, it must have been made for this architecture.
 #
 prefix_arch = "win64"
 
@@ -71,6 +155,23 @@ prefix_arch = "win64"
 #
 # base_prefix = "path/to/existing/wineprefix/on/host"
 
+# where the app keeps each user's copy of the wineprefix. The wineprefix in the app
+# is never changed, so the app can run from a read-only volume and keeps its code
+# signature. {data_dir} is ~/Library/Application Support on macOS (XDG_DATA_HOME on
+# Linux), {identifier} is the bundle identifier and ~ the user's home directory.
+# "bundle" uses the wineprefix right inside the app like older versions did.
+# Default "{data_dir}/{identifier}/prefix".
+#
+location = "{data_dir}/{identifier}/prefix"
+
+# what happens to a user's wineprefix when they start a new build of the app with a
+# different wineprefix. "replace" starts over from the new one, "keep" keeps using the
+# old one and "merge" starts from the new one but brings drive_c/users and user.reg
+# over from the old one, so saves and settings survive. The old wineprefix is backed
+# up next to it as prefix.backup. Default "merge".
+#
+upgrade = "merge"
+
 # Sandbox the wineprefix, default true.
 # Can also be enabled by specifying "sandbox" as a verb to winetricks
 #
@@ -85,14 +186,25 @@ install_mono = false
 #
 install_gecko = false
 
-# To save some space, you can delete C:/windows/Installers directory, default true
+# To save some space, you can delete what's in C:/windows/Installer, the copies of
+# installers windows keeps for repairs and uninstalls. Default true
 #
 delete_installers = true
 
+# more to remove from the wineprefix once everything is installed, as glob patterns on
+# drive C:. * matches within a directory and ** across directories, case doesn't
+# matter. How much each pattern saved is shown during the build. Default empty
+#
+# prune = ["c:/windows/temp/*", "c:/**/*.log", "c:/**/*.dmp"]
+
 # Compress the wineprefix after the app has been built. When the user first starts the app,
-# the prefix will be uncompressed. Default true.
+# the prefix will be uncompressed. Either "none", "gzip", "xz" or "zstd", or a table
+# with a format and a level: 0-9 for gzip and xz (default 6), 1-22 for zstd (default 19).
+# zstd archives are the fastest to uncompress. true means "gzip" and false "none".
+# Default "gzip".
 #
-compress_wineprefix = true
+compress_wineprefix = "gzip"
+# compress_wineprefix = { format = "zstd", level = 19 }
 
 # if you want to copy any files or folders over to the wineprefix,
 # you can specify the file/folder on the host/wineprefix to copy into the
@@ -114,21 +226,54 @@ from = "c:/in/wineprefix/another/file.txt"
 to = "c:/in/wineprefix/another/file.bak.txt"
 post_install = true
 
+# "from" can also be a glob on the host, every match goes into the "to" folder.
+# "exclude" leaves out anything matching one of its patterns, either by name anywhere
+# or by path inside the volume. "mode" is "copy" (the default), "symlink" or
+# "hardlink"; linking is quicker for big folders while you work on the app, but
+# symlinked files only exist on your machine, so copy them for the app you ship.
+# "file_mode" and "dir_mode" set the permissions of copied files and folders.
+#
+[[wine.volume]]
+from = "/on/host/assets/*"
+to = "c:/in/wineprefix/assets"
+exclude = [".DS_Store", "raw/**"]
+mode = "copy"
+file_mode = 0o644
+dir_mode = 0o755
+
 # programs that you want to run/install in the wineprefix, default empty.
-# You can specify programs on the host or in the wineprefix
+# You can specify programs on the host or in the wineprefix, or the name of a program
+# that comes with wine, like "regedit". Each one has to finish
+# before the next one starts, and once they're all done boxwine waits for anything
+# they started to finish too.
 #
 [[wine.run]]
 program = "on/host/Setup.exe"
 
-# Use $WINEPREFIX variable to access the C:/ in wineprefix
+# Use a Windows path for programs in the wineprefix
 [[wine.run]]
-program = "$WINEPREFIX/in/wine/some-file.exe"  
+program = "c:/in/wine/some-file.exe"
 
 # use a list to specify arguments that you want to pass in
 [[wine.run]]
 program = "on/host/other-file.exe"
 args = ["--some-arg true", "--another-one"]
 
+# "env" sets environment variables and "cwd" the working directory, a Windows path
+# or one on the host. "timeout" fails the build when the program takes more seconds
+# than that, and "success_codes" are the exit codes that don't, default [0]. With
+# "wait" = false the next program starts right away, default true.
+# app.entrypoint takes "env" and "cwd" too, but it runs on the user's Mac, so its
+# "cwd" is a Windows path or starts with ${WINEPREFIX} or ${DRIVE_C}.
+#
+[[wine.run]]
+program = "on/host/installer.exe"
+env = { LANG = "en_US.UTF-8" }
+cwd = "c:/windows/temp"
+timeout = 600
+success_codes = [0, 3010]
+wait = true
+
 
 [winetricks]
 # if you want to install any verbs from winetricks, you can
@@ -139,7 +284,24 @@ verbs = [
 "directplay"   # for local multiplayer
 ]
 
-# if you want to bundle winetricks into the app, default false
+# variants of the app, like one to debug it and one to ship. A profile overrides any
+# key of the config the same way a config overrides the one it extends, but only when
+# it's picked with `boxwine create --profile <name>`. Its suffix goes into the name of
+# the app, "My App-debug.app", and its bundle identifier, "com.example.my-app.debug",
+# so it can be installed next to the others. suffix defaults to the name of the
+# profile, "" leaves both as they are. Default empty.
+#
+# [profile.debug.wine.prefix]
+# compress_wineprefix = false
+# delete_installers = false
+#
+# [profile.debug.app.entrypoint.env]
+# WINEDEBUG = "err+all,fixme+all"
+#
+# [profile.release]
+# suffix = ""
 #
-bundle = false
+# [profile.release.wine.prefix]
+# compress_wineprefix = { format = "zstd", level = 19 }
+# prune = ["c:/windows/temp/*", "c:/**/*.log"]
 "###;
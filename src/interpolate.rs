@@ -0,0 +1,81 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// The variable for the host path of the wineprefix
+pub const WINEPREFIX_VARIABLE: &str = "WINEPREFIX";
+
+/// The variable for the host path of drive C: in the wineprefix
+pub const DRIVE_C_VARIABLE: &str = "DRIVE_C";
+
+/// How text that's being filled in treats a variable
+pub enum Expansion {
+    /// Put this in place of the variable
+    Value(String),
+
+    /// Leave the variable as it is, to be filled in later
+    Keep,
+}
+
+/// Fill in the variables in `text`, which are written `${name}`, with what `expand` says.
+/// `$${` is a literal `${`, and a `$` anywhere else is just a `$`.
+///
+/// Text that gets filled in again later, like the entrypoint by the launcher, keeps its
+/// `$${` escapes when `keep_escapes` is set, so only the last time unescapes them.
+pub fn interpolate(
+    text: &str,
+    keep_escapes: bool,
+    mut expand: impl FnMut(&str) -> Result<Expansion>,
+) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("$${") {
+            result.push_str(if keep_escapes { "$${" } else { "${" });
+            rest = &rest[3..];
+        } else if let Some(variable) = rest.strip_prefix("${") {
+            let end = match variable.find('}') {
+                Some(end) => end,
+                None => bail!("{} has a ${{ without a }} after it", text),
+            };
+            let name = &variable[..end];
+            if name.is_empty() {
+                bail!("{} has a variable without a name", text);
+            }
+
+            match expand(name)? {
+                Expansion::Value(value) => result.push_str(&value),
+                Expansion::Keep => result.push_str(&rest[..end + 3]),
+            }
+            rest = &variable[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Fill in the wineprefix variables that create leaves in the entrypoint, for the
+/// wineprefix at `wineprefix_path`. create made sure everything else in it is valid.
+pub fn fill_in_wineprefix(text: &str, wineprefix_path: &Path) -> String {
+    let filled_in = interpolate(text, false, |name| {
+        Ok(match name {
+            WINEPREFIX_VARIABLE => Expansion::Value(wineprefix_path.to_string_lossy().into_owned()),
+            DRIVE_C_VARIABLE => Expansion::Value(
+                wineprefix_path
+                    .join("drive_c")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            _ => Expansion::Keep,
+        })
+    });
+
+    filled_in.unwrap_or_else(|_| text.to_string())
+}
@@ -0,0 +1,742 @@
+use crate::archive;
+use crate::copy::{copy_dir, copy_path, remove_path};
+use crate::interpolate::fill_in_wineprefix;
+use crate::manifest::{Manifest, Persist, UpgradePolicy};
+use crate::winpath::WindowsPath;
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The file in a user's wineprefix with the version it was set up from
+const VERSION_FILE_NAME: &str = ".boxwine-version";
+
+/// What a merge upgrade brings over from the old wineprefix: the user's files, saves and
+/// settings
+const USER_DATA_PATHS: &[&str] = &["drive_c/users", "user.reg"];
+
+/// The directory in the app's data directory that persisted user data is kept in
+const PERSIST_DIR_NAME: &str = "persist";
+
+/// Where persisted registry keys are saved, in the persist directory
+const REGISTRY_DIR_NAME: &str = "registry";
+
+/// Starts the app described by the manifest of the bundle it lives in
+pub struct Launcher {
+    /// The bundle's `Contents/MacOS` directory
+    macos_path: PathBuf,
+    manifest: Manifest,
+
+    /// Where this user's wineprefix lives
+    wineprefix_path: PathBuf,
+}
+
+impl Launcher {
+    /// Find the app bundle around `executable`, which is expected to live in
+    /// `<app>/Contents/MacOS`
+    pub fn from_executable(executable: &Path) -> Result<Launcher> {
+        let macos_path = executable
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no parent directory", executable.display()))?;
+        let app_path = macos_path.join("../..");
+        let manifest = Manifest::load(&app_path)?;
+
+        let wineprefix_path = match &manifest.wineprefix_location {
+            Some(location) => expand_location(location, &manifest.identifier)?,
+            None => macos_path.join(&manifest.wineprefix),
+        };
+
+        Ok(Launcher {
+            macos_path: macos_path.to_path_buf(),
+            manifest,
+            wineprefix_path,
+        })
+    }
+
+    pub fn wineprefix_path(&self) -> PathBuf {
+        self.wineprefix_path.clone()
+    }
+
+    pub fn wine_path(&self) -> PathBuf {
+        wine_binary(&self.macos_path.join(&self.manifest.wine_dir))
+    }
+
+    /// Set up the user's wineprefix from the one in the bundle if this is the first
+    /// time the app is started, or upgrade it if the bundle ships a different version.
+    /// The bundle itself is never changed, so it can live on a read-only volume and keep
+    /// its code signature.
+    pub fn prepare_wineprefix(&self) -> Result<()> {
+        let wineprefix_path = self.wineprefix_path();
+        let upgrade = self.pending_upgrade()?;
+        if wineprefix_path.exists() && upgrade.is_none() {
+            return Ok(());
+        }
+
+        if let Some(parent) = wineprefix_path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Creating {}", parent.display()))?;
+        }
+
+        // Unpack next to the final location and move it into place when done, so an
+        // interrupted start never leaves a half-unpacked prefix behind
+        let unpack_path = wineprefix_path.with_extension("unpacking");
+        remove_path(&unpack_path)?;
+
+        match &self.manifest.wineprefix_archive {
+            Some(archive) => {
+                let archive_path = self.macos_path.join(archive);
+                let format = self.manifest.wineprefix_compression;
+
+                eprintln!("Uncompressing wineprefix ...");
+                archive::unpack_wineprefix(&archive_path, &unpack_path, format)
+                    .with_context(|| format!("Uncompressing {}", archive_path.display()))?;
+            }
+            None => {
+                let bundled_path = self.macos_path.join(&self.manifest.wineprefix);
+
+                eprintln!("Copying wineprefix ...");
+                copy_dir(&bundled_path, &unpack_path)
+                    .with_context(|| format!("Copying {}", bundled_path.display()))?;
+            }
+        }
+
+        if upgrade == Some(UpgradePolicy::Merge) {
+            eprintln!("Bringing your data over from the old wineprefix ...");
+            for user_data_path in USER_DATA_PATHS {
+                let old_path = wineprefix_path.join(user_data_path);
+                if fs::symlink_metadata(&old_path).is_ok() {
+                    let new_path = unpack_path.join(user_data_path);
+                    remove_path(&new_path)?;
+                    copy_path(&old_path, &new_path)?;
+                }
+            }
+        }
+
+        if let Some(version) = &self.manifest.wineprefix_version {
+            let version_path = unpack_path.join(VERSION_FILE_NAME);
+            fs::write(&version_path, version)
+                .with_context(|| format!("Writing {}", version_path.display()))?;
+        }
+
+        // The old wineprefix is kept until the next upgrade, in case anything that
+        // mattered didn't make it over
+        if upgrade.is_some() {
+            let backup_path = wineprefix_path.with_extension("backup");
+            remove_path(&backup_path)?;
+            fs::rename(&wineprefix_path, &backup_path)
+                .with_context(|| format!("Backing up wineprefix to {}", backup_path.display()))?;
+            eprintln!("The old wineprefix is in {}", backup_path.display());
+        }
+
+        fs::rename(&unpack_path, &wineprefix_path)
+            .with_context(|| format!("Moving wineprefix to {}", wineprefix_path.display()))?;
+        eprintln!("Done!");
+
+        Ok(())
+    }
+
+    /// How the user's wineprefix needs to be upgraded, if it exists and was set up from
+    /// another version than the one in the bundle
+    fn pending_upgrade(&self) -> Result<Option<UpgradePolicy>> {
+        // A wineprefix in the bundle is replaced along with the bundle
+        if self.manifest.wineprefix_location.is_none() {
+            return Ok(None);
+        }
+        let version = match &self.manifest.wineprefix_version {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        let wineprefix_path = self.wineprefix_path();
+        if !wineprefix_path.exists() {
+            return Ok(None);
+        }
+
+        // Wineprefixes set up before versions were stamped have no version file
+        let version_path = wineprefix_path.join(VERSION_FILE_NAME);
+        let installed_version = match fs::read_to_string(&version_path) {
+            Ok(installed_version) => Some(installed_version),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", version_path.display())),
+        };
+        if installed_version.as_deref() == Some(version.as_str()) {
+            return Ok(None);
+        }
+
+        match self.manifest.wineprefix_upgrade {
+            UpgradePolicy::Keep => Ok(None),
+            policy => Ok(Some(policy)),
+        }
+    }
+
+    /// Link every persisted path in the wineprefix to the user's data outside of it.
+    /// The first time a path is persisted, whatever is in the wineprefix becomes that
+    /// data. After that, the user's data always wins over what a fresh or upgraded
+    /// wineprefix comes with.
+    pub fn link_persisted_paths(&self) -> Result<()> {
+        let wineprefix_path = self.wineprefix_path();
+        let persist_path = self.persist_path()?;
+
+        let paths: Vec<&String> = self
+            .manifest
+            .persist
+            .iter()
+            .filter_map(|entry| match entry {
+                Persist::Path { path } => Some(path),
+                Persist::Registry { .. } => None,
+            })
+            .collect();
+        // Only apps that persist paths need to know who the user is
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let user = user_name()?;
+
+        for path in paths {
+            let relative_path = persisted_path(path, &user)?;
+            let link_path = wineprefix_path.join(&relative_path);
+            let data_path = persist_path.join(&relative_path);
+
+            if fs::read_link(&link_path).is_ok_and(|target| target == data_path) {
+                continue;
+            }
+
+            if let Some(parent) = data_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Creating {}", parent.display()))?;
+            }
+            if fs::symlink_metadata(&link_path).is_ok() {
+                if fs::symlink_metadata(&data_path).is_err() {
+                    move_path(&link_path, &data_path)?;
+                } else {
+                    remove_path(&link_path)?;
+                }
+            } else if fs::symlink_metadata(&data_path).is_err() {
+                // Nothing to go on, so expect the app to keep a directory there
+                fs::create_dir(&data_path)
+                    .with_context(|| format!("Creating {}", data_path.display()))?;
+            }
+
+            if let Some(parent) = link_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Creating {}", parent.display()))?;
+            }
+            symlink(&data_path, &link_path)
+                .with_context(|| format!("Linking {} to {}", path, data_path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether any registry keys are persisted, which means wine has to be waited for
+    /// to save them when the app quits
+    pub fn persists_registry(&self) -> bool {
+        self.manifest
+            .persist
+            .iter()
+            .any(|entry| matches!(entry, Persist::Registry { .. }))
+    }
+
+    /// Put the persisted registry keys saved when the app last quit back into the
+    /// registry
+    pub fn import_registry(&self) -> Result<()> {
+        for (_, registry_path) in self.registry_paths()? {
+            if !registry_path.exists() {
+                continue;
+            }
+
+            let status = self
+                .wine_command()
+                .args(["reg", "import"])
+                .arg(&registry_path)
+                .stdout(Stdio::null())
+                .status()
+                .with_context(|| format!("Starting {}", self.wine_path().display()))?;
+            if !status.success() {
+                bail!(
+                    "Importing {} failed with {}",
+                    registry_path.display(),
+                    status
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save the persisted registry keys. Keys the app hasn't created yet are skipped.
+    pub fn export_registry(&self) -> Result<()> {
+        let registry_paths = self.registry_paths()?;
+        if let Some((_, registry_path)) = registry_paths.first() {
+            if let Some(parent) = registry_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Creating {}", parent.display()))?;
+            }
+        }
+
+        for (key, registry_path) in registry_paths {
+            let status = self
+                .wine_command()
+                .args(["reg", "export", key])
+                .arg(&registry_path)
+                .arg("/y")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .with_context(|| format!("Starting {}", self.wine_path().display()))?;
+            if !status.success() {
+                eprintln!("Not saving {}, it isn't in the registry", key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The command that starts the entrypoint in wine
+    pub fn command(&self) -> Command {
+        let entrypoint = &self.manifest.entrypoint;
+        let wineprefix_path = self.wineprefix_path();
+        let fill_in = |text: &String| fill_in_wineprefix(text, &wineprefix_path);
+
+        let mut command = self.wine_command();
+        command
+            .arg(fill_in(&entrypoint.program))
+            .args(entrypoint.args.iter().map(fill_in))
+            .envs(
+                entrypoint
+                    .env
+                    .iter()
+                    .map(|(name, value)| (name, fill_in(value))),
+            );
+
+        // Windows paths are in the user's wineprefix, create made sure they're valid
+        if let Some(cwd) = entrypoint.cwd.as_ref().map(fill_in) {
+            match WindowsPath::parse(&cwd) {
+                Ok(cwd) => command.current_dir(cwd.host_path(&wineprefix_path)),
+                Err(_) => command.current_dir(cwd),
+            };
+        }
+
+        command
+    }
+
+    /// A wine command that runs in the user's wineprefix
+    fn wine_command(&self) -> Command {
+        let mut command = Command::new(self.wine_path());
+        command
+            .env("WINEPREFIX", self.wineprefix_path())
+            .env("WINEDLLOVERRIDES", &self.manifest.dll_overrides);
+
+        command
+    }
+
+    /// Where the user's persisted data is kept
+    fn persist_path(&self) -> Result<PathBuf> {
+        Ok(data_dir()?
+            .join(&self.manifest.identifier)
+            .join(PERSIST_DIR_NAME))
+    }
+
+    /// Every persisted registry key, with the file it's saved to
+    fn registry_paths(&self) -> Result<Vec<(&str, PathBuf)>> {
+        let registry_dir = self.persist_path()?.join(REGISTRY_DIR_NAME);
+
+        Ok(self
+            .manifest
+            .persist
+            .iter()
+            .filter_map(|entry| match entry {
+                Persist::Registry { registry } => Some(registry.as_str()),
+                Persist::Path { .. } => None,
+            })
+            .map(|key| {
+                let file_name: String = key
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect();
+                (key, registry_dir.join(format!("{}.reg", file_name)))
+            })
+            .collect())
+    }
+}
+
+/// Where a persisted path like `c:/users/<user>/Saved Games` is, relative to the
+/// wineprefix, with `<user>` filled in with `user`. Only paths on drive C: can be
+/// persisted, the other drives aren't part of the wineprefix.
+pub fn persisted_path(path: &str, user: &str) -> Result<PathBuf> {
+    let windows_path = WindowsPath::parse(&path.replace("<user>", user))
+        .with_context(|| format!("Can't persist {}", path))?;
+    if windows_path.drive() != 'c' {
+        bail!(
+            "Can't persist {}, only paths on drive C: like c:/users/<user>/Saved Games can be",
+            path
+        );
+    }
+    if windows_path.is_root() {
+        bail!("Can't persist all of drive C:, only paths in it");
+    }
+
+    let mut persisted_path = PathBuf::from("drive_c");
+    persisted_path.extend(windows_path.components());
+
+    Ok(persisted_path)
+}
+
+/// Move `from` to `to`, copying when they're on different volumes
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        copy_path(from, to)
+            .with_context(|| format!("Moving {} to {}", from.display(), to.display()))?;
+        remove_path(from)?;
+    }
+
+    Ok(())
+}
+
+/// The name of the user running the app, which wine names their profile after
+fn user_name() -> Result<String> {
+    env::var("USER")
+        .ok()
+        .filter(|user| !user.is_empty())
+        .ok_or_else(|| anyhow!("Can't find the user name, $USER isn't set"))
+}
+
+/// Fill in a wineprefix location from the manifest: `~` is the home directory,
+/// `{data_dir}` the directory apps keep their data in and `{identifier}` the bundle
+/// identifier
+fn expand_location(location: &str, identifier: &str) -> Result<PathBuf> {
+    let mut expanded = location.replace("{identifier}", identifier);
+    if expanded.contains("{data_dir}") {
+        expanded = expanded.replace("{data_dir}", &data_dir()?.to_string_lossy());
+    }
+    if let Some(rest) = expanded.strip_prefix("~/") {
+        expanded = home_dir()?.join(rest).to_string_lossy().to_string();
+    }
+
+    let path = PathBuf::from(expanded);
+    if !path.is_absolute() {
+        bail!(
+            "The wineprefix location {} isn't an absolute path",
+            location
+        );
+    }
+
+    Ok(path)
+}
+
+/// `~/Library/Application Support` on macOS, and `$XDG_DATA_HOME` falling back to
+/// `~/.local/share` everywhere else
+pub fn data_dir() -> Result<PathBuf> {
+    if cfg!(target_os = "macos") {
+        return Ok(home_dir()?.join("Library/Application Support"));
+    }
+
+    match env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(data_home) => Ok(PathBuf::from(data_home)),
+        None => Ok(home_dir()?.join(".local/share")),
+    }
+}
+
+fn home_dir() -> Result<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("Can't find the home directory, $HOME isn't set"))
+}
+
+/// The wine binary in a wine directory. Some builds only come with `wine64`.
+pub fn wine_binary(wine_dir: &Path) -> PathBuf {
+    let wine_path = wine_dir.join("bin/wine");
+    let wine64_path = wine_dir.join("bin/wine64");
+
+    if !wine_path.exists() && wine64_path.exists() {
+        wine64_path
+    } else {
+        wine_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{Compression, Format};
+    use crate::manifest::{Entrypoint, MANIFEST_PATH};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::{Mutex, MutexGuard};
+    use tempfile::TempDir;
+
+    const IDENTIFIER: &str = "com.boxwine.test";
+    const USER: &str = "tester";
+    const SAVES: &str = "c:/users/<user>/Saved Games/Test";
+
+    /// The launcher finds the user's directories in the environment, so the tests that
+    /// set it up take turns
+    static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+    /// An app bundle with a wine that prints how it was started, for a user whose home
+    /// and data directories are in a temporary directory
+    struct TestApp {
+        dir: TempDir,
+        _environment: MutexGuard<'static, ()>,
+    }
+
+    impl TestApp {
+        fn new() -> TestApp {
+            let environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+            let dir = TempDir::new().unwrap();
+            env::set_var("HOME", dir.path().join("home"));
+            env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+            env::set_var("USER", USER);
+
+            let app = TestApp {
+                dir,
+                _environment: environment,
+            };
+            let wine_path = app.macos_path().join("wine/bin/wine");
+            fs::create_dir_all(wine_path.parent().unwrap()).unwrap();
+            fs::write(
+                &wine_path,
+                "#!/bin/sh\nprintf '%s\\n' \"$WINEPREFIX\" \"$PWD\" \"$@\"\n",
+            )
+            .unwrap();
+            fs::set_permissions(&wine_path, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::create_dir_all(app.app_path().join(MANIFEST_PATH).parent().unwrap()).unwrap();
+
+            app
+        }
+
+        fn app_path(&self) -> PathBuf {
+            self.dir.path().join("Test.app")
+        }
+
+        fn macos_path(&self) -> PathBuf {
+            self.app_path().join("Contents/MacOS")
+        }
+
+        fn wineprefix_path(&self) -> PathBuf {
+            self.dir.path().join("data").join(IDENTIFIER).join("prefix")
+        }
+
+        fn persist_path(&self) -> PathBuf {
+            self.dir.path().join("data").join(IDENTIFIER).join("persist")
+        }
+
+        /// Put version `version` of the app in the bundle, with a wineprefix that has
+        /// `files` in it, and start it up to the point where wine would run
+        fn ship(
+            &self,
+            version: &str,
+            upgrade: UpgradePolicy,
+            compressed: bool,
+            files: &[(&str, &str)],
+        ) -> Launcher {
+            let macos_path = self.macos_path();
+            let bundled_path = macos_path.join("wineprefix");
+            let archive_path = macos_path.join("wineprefix.tar.zst");
+            remove_path(&bundled_path).unwrap();
+            remove_path(&archive_path).unwrap();
+
+            for (path, contents) in files {
+                let path = bundled_path.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            let compression = Compression {
+                format: Format::Zstd,
+                level: 1,
+            };
+            if compressed {
+                archive::pack(&bundled_path, &archive_path, compression).unwrap();
+                remove_path(&bundled_path).unwrap();
+            }
+
+            let manifest = Manifest {
+                name: "Test".to_string(),
+                wine_dir: "wine".to_string(),
+                identifier: IDENTIFIER.to_string(),
+                wineprefix: "wineprefix".to_string(),
+                wineprefix_location: Some("{data_dir}/{identifier}/prefix".to_string()),
+                wineprefix_version: Some(version.to_string()),
+                wineprefix_upgrade: upgrade,
+                wineprefix_archive: Some("wineprefix.tar.zst".to_string()).filter(|_| compressed),
+                wineprefix_compression: Some(compression.format).filter(|_| compressed),
+                dll_overrides: String::new(),
+                persist: vec![Persist::Path {
+                    path: SAVES.to_string(),
+                }],
+                entrypoint: Entrypoint {
+                    program: "${DRIVE_C}/game.exe".to_string(),
+                    args: vec!["--data".to_string(), "${WINEPREFIX}/data".to_string()],
+                    cwd: Some(r"C:\users".to_string()),
+                    env: Default::default(),
+                },
+            };
+            manifest.write(&self.app_path()).unwrap();
+
+            let launcher = Launcher::from_executable(&macos_path.join("launcher")).unwrap();
+            launcher.prepare_wineprefix().unwrap();
+            launcher.link_persisted_paths().unwrap();
+            launcher
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.wineprefix_path().join(path)).unwrap()
+        }
+
+        fn read_backup(&self, path: &str) -> String {
+            fs::read_to_string(self.wineprefix_path().with_extension("backup").join(path))
+                .unwrap()
+        }
+    }
+
+    fn saves_path() -> String {
+        format!("drive_c/users/{}/Saved Games/Test", USER)
+    }
+
+    #[test]
+    fn unpacks_the_wineprefix_on_the_first_start() {
+        let app = TestApp::new();
+        let launcher = app.ship(
+            "1",
+            UpgradePolicy::Merge,
+            true,
+            &[("system.reg", "system 1"), ("drive_c/users/readme.txt", "hi")],
+        );
+
+        assert_eq!(launcher.wineprefix_path(), app.wineprefix_path());
+        assert_eq!(app.read("system.reg"), "system 1");
+        assert_eq!(app.read(VERSION_FILE_NAME), "1");
+        assert!(!app.wineprefix_path().with_extension("backup").exists());
+
+        let output = launcher.command().output().unwrap();
+        assert!(output.status.success());
+        let wineprefix_path = app.wineprefix_path().canonicalize().unwrap();
+        let expected = [
+            app.wineprefix_path().display().to_string(),
+            wineprefix_path.join("drive_c/users").display().to_string(),
+            app.wineprefix_path()
+                .join("drive_c/game.exe")
+                .display()
+                .to_string(),
+            "--data".to_string(),
+            app.wineprefix_path().join("data").display().to_string(),
+        ];
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().lines().collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    /// Ship version 1, have the user save a game and change their settings, then ship
+    /// version 2 with `upgrade`
+    fn upgrade(upgrade: UpgradePolicy) -> TestApp {
+        let app = TestApp::new();
+        app.ship(
+            "1",
+            upgrade,
+            false,
+            &[
+                ("system.reg", "system 1"),
+                ("user.reg", "user 1"),
+                ("drive_c/windows/win.ini", "1"),
+            ],
+        );
+        fs::write(app.wineprefix_path().join("user.reg"), "changed by the user").unwrap();
+        let documents_path = app.wineprefix_path().join("drive_c/users/tester/Documents");
+        fs::create_dir_all(&documents_path).unwrap();
+        fs::write(documents_path.join("notes.txt"), "notes").unwrap();
+
+        app.ship(
+            "2",
+            upgrade,
+            false,
+            &[
+                ("system.reg", "system 2"),
+                ("user.reg", "user 2"),
+                ("drive_c/windows/win.ini", "2"),
+            ],
+        );
+        app
+    }
+
+    #[test]
+    fn keep_upgrades_keep_the_old_wineprefix() {
+        let app = upgrade(UpgradePolicy::Keep);
+
+        assert_eq!(app.read("system.reg"), "system 1");
+        assert_eq!(app.read("user.reg"), "changed by the user");
+        assert_eq!(app.read(VERSION_FILE_NAME), "1");
+        assert!(!app.wineprefix_path().with_extension("backup").exists());
+    }
+
+    #[test]
+    fn replace_upgrades_start_over_and_back_up() {
+        let app = upgrade(UpgradePolicy::Replace);
+
+        assert_eq!(app.read("system.reg"), "system 2");
+        assert_eq!(app.read("user.reg"), "user 2");
+        assert_eq!(app.read(VERSION_FILE_NAME), "2");
+        assert!(!app
+            .wineprefix_path()
+            .join("drive_c/users/tester/Documents")
+            .exists());
+
+        assert_eq!(app.read_backup("user.reg"), "changed by the user");
+        assert_eq!(
+            app.read_backup("drive_c/users/tester/Documents/notes.txt"),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn merge_upgrades_bring_the_user_data_over() {
+        let app = upgrade(UpgradePolicy::Merge);
+
+        assert_eq!(app.read("system.reg"), "system 2");
+        assert_eq!(app.read("user.reg"), "changed by the user");
+        assert_eq!(
+            app.read("drive_c/users/tester/Documents/notes.txt"),
+            "notes"
+        );
+        assert_eq!(app.read(VERSION_FILE_NAME), "2");
+        assert_eq!(app.read_backup("system.reg"), "system 1");
+    }
+
+    #[test]
+    fn persisted_paths_survive_upgrades() {
+        let app = TestApp::new();
+        let saves = saves_path();
+        let default_save = format!("{}/default.sav", saves);
+        app.ship(
+            "1",
+            UpgradePolicy::Replace,
+            false,
+            &[(default_save.as_str(), "shipped with 1")],
+        );
+
+        // What the wineprefix came with became the user's data, and is linked back in
+        let link_path = app.wineprefix_path().join(&saves);
+        assert_eq!(
+            fs::read_link(&link_path).unwrap(),
+            app.persist_path().join(&saves)
+        );
+        assert_eq!(app.read(&default_save), "shipped with 1");
+        fs::write(link_path.join("game.sav"), "progress").unwrap();
+
+        app.ship(
+            "2",
+            UpgradePolicy::Replace,
+            false,
+            &[(default_save.as_str(), "shipped with 2")],
+        );
+
+        // The user's data wins over what the new wineprefix comes with
+        assert_eq!(
+            fs::read_link(&link_path).unwrap(),
+            app.persist_path().join(&saves)
+        );
+        assert_eq!(app.read(&default_save), "shipped with 1");
+        assert_eq!(app.read(&format!("{}/game.sav", saves)), "progress");
+    }
+}
@@ -0,0 +1,9 @@
+//! The parts of boxwine that are shared between the `boxwine` command line tool
+//! and the launcher that gets bundled into every app.
+
+pub mod archive;
+pub mod copy;
+pub mod interpolate;
+pub mod launcher;
+pub mod manifest;
+pub mod winpath;
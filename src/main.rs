@@ -1,13 +1,19 @@
 use anyhow::Result;
-use clap::Clap;
+use clap::{Parser, Subcommand};
 
+mod cache;
 mod config;
 mod create;
+mod download;
 mod files;
 mod init;
+mod process;
+mod resolve;
+mod validate;
+mod variables;
 
 /// Box up your Wine apps and turn them into Mac Apps.
-#[derive(Clap)]
+#[derive(Parser)]
 #[clap(version = clap::crate_version!())]
 struct Opts {
     /// A level of verbosity, and can be used multiple times
@@ -17,10 +23,13 @@ struct Opts {
     subcmd: SubCommand,
 }
 
-#[derive(Clap)]
+#[derive(Subcommand)]
 enum SubCommand {
+    Cache(cache::Cache),
+    Config(resolve::ConfigCommand),
     Create(create::Create),
     Init(init::Init),
+    Validate(validate::Validate),
 }
 
 fn main() -> Result<()> {
@@ -28,7 +37,10 @@ fn main() -> Result<()> {
 
     // Dispatch handlers for subcommands
     match opts.subcmd {
+        SubCommand::Cache(cache_opts) => cache::cache(cache_opts),
+        SubCommand::Config(config_opts) => resolve::config(config_opts),
         SubCommand::Create(create_opts) => create::create(create_opts),
         SubCommand::Init(init_opts) => init::init(init_opts),
+        SubCommand::Validate(validate_opts) => validate::validate(validate_opts),
     }
 }
@@ -0,0 +1,120 @@
+use crate::archive::Format;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Where the manifest lives, relative to the root of the app bundle
+pub const MANIFEST_PATH: &str = "Contents/Resources/boxwine.toml";
+
+/// Describes an app bundle to the launcher. `create` writes it into the bundle and
+/// the launcher reads it back on every start.
+///
+/// All paths are relative to the bundle's `Contents/MacOS` directory.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// Name of the app
+    pub name: String,
+
+    /// Directory containing the wine build, with wine at `bin/wine`
+    pub wine_dir: String,
+
+    /// Bundle identifier of the app
+    #[serde(default)]
+    pub identifier: String,
+
+    /// Directory of the wineprefix in the bundle, when it isn't compressed
+    pub wineprefix: String,
+
+    /// Where every user gets their own copy of the wineprefix, with `~`, `{data_dir}`
+    /// and `{identifier}` still to be filled in. Without it, the wineprefix is used
+    /// in the bundle.
+    pub wineprefix_location: Option<String>,
+
+    /// Identifies the contents of the wineprefix this bundle ships. The launcher upgrades
+    /// a user's wineprefix that was set up from a different version.
+    pub wineprefix_version: Option<String>,
+
+    /// What to do with a user's wineprefix from a different version
+    #[serde(default)]
+    pub wineprefix_upgrade: UpgradePolicy,
+
+    /// The compressed wineprefix, if the prefix was compressed when the app was created
+    pub wineprefix_archive: Option<String>,
+
+    /// How the wineprefix archive is compressed. Bundles made before this was recorded
+    /// have it detected from the archive.
+    pub wineprefix_compression: Option<Format>,
+
+    /// Value of WINEDLLOVERRIDES to start wine with
+    pub dll_overrides: String,
+
+    /// User data that lives outside of the wineprefix, so nothing that happens to the
+    /// wineprefix can lose it
+    #[serde(default)]
+    pub persist: Vec<Persist>,
+
+    /// The program to start
+    pub entrypoint: Entrypoint,
+}
+
+/// How the launcher brings a user's wineprefix up to the version in the bundle
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UpgradePolicy {
+    /// Start over from the new wineprefix
+    Replace,
+
+    /// Keep using the old wineprefix
+    Keep,
+
+    /// Start from the new wineprefix, but bring the user's data over from the old one
+    #[default]
+    Merge,
+}
+
+/// Something in the wineprefix that holds user data
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Persist {
+    /// A file or directory, as a path on drive C: like `c:/users/<user>/Saved Games/Foo`,
+    /// where `<user>` is the name of the user running the app
+    Path { path: String },
+
+    /// A registry key like `HKEY_CURRENT_USER\Software\Foo`
+    Registry { registry: String },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entrypoint {
+    /// Program to run, already in a form wine accepts
+    pub program: String,
+    pub args: Vec<String>,
+
+    /// Directory to start the program in, a Windows path or one in the user's wineprefix
+    pub cwd: Option<String>,
+
+    /// Environment variables to start the program with. Tables have to come last in the
+    /// manifest.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn load(app_path: &Path) -> Result<Manifest> {
+        let manifest_path = app_path.join(MANIFEST_PATH);
+        let contents = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Reading {}", manifest_path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("Parsing {}", manifest_path.display()))
+    }
+
+    pub fn write(&self, app_path: &Path) -> Result<()> {
+        let manifest_path = app_path.join(MANIFEST_PATH);
+        let contents = toml::to_string(self).with_context(|| "Serializing bundle manifest")?;
+
+        fs::write(&manifest_path, contents)
+            .with_context(|| format!("Writing {}", manifest_path.display()))
+    }
+}
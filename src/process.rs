@@ -0,0 +1,214 @@
+use anyhow::{bail, Context, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use wait_timeout::ChildExt;
+
+/// How many lines at the end of a failed command's output end up in the error
+const OUTPUT_TAIL_LINES: usize = 20;
+
+/// Where the output of every command run during a build is written, one log file per step.
+/// Steps reused from an earlier build keep the log of the build that ran them.
+pub struct BuildLog {
+    log_dir: PathBuf,
+
+    /// Steps that wrote to their log in this build
+    started: RefCell<HashSet<String>>,
+}
+
+impl BuildLog {
+    pub fn create(log_dir: PathBuf) -> Result<BuildLog> {
+        fs::create_dir_all(&log_dir)
+            .with_context(|| format!("Creating log directory {}", log_dir.display()))?;
+
+        Ok(BuildLog {
+            log_dir,
+            started: RefCell::new(HashSet::new()),
+        })
+    }
+
+    pub fn step_log_path(&self, step: &str) -> PathBuf {
+        self.log_dir.join(format!("{}.log", step))
+    }
+
+    /// Open the log of `step` to append to it. The first time in a build, the step is
+    /// running again, so the log of its last run is thrown away.
+    pub fn open(&self, step: &str) -> Result<fs::File> {
+        let log_path = self.step_log_path(step);
+        let first_run = self.started.borrow_mut().insert(step.to_string());
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .truncate(false)
+            .open(&log_path)
+            .and_then(|log| {
+                if first_run {
+                    log.set_len(0)?;
+                }
+                Ok(log)
+            })
+            .with_context(|| format!("Opening log file {}", log_path.display()))
+    }
+
+    /// Run `command` to completion with its stdout and stderr appended to the log of
+    /// `step`. A non-zero exit is an error that includes the end of the output.
+    pub fn run(&self, step: &str, command: &mut Command) -> Result<()> {
+        self.run_with(step, command, None, &[0])
+    }
+
+    /// Like `run`, but exiting with any of `success_codes` is a success, and taking
+    /// longer than `timeout` is an error. The command is killed when it times out.
+    pub fn run_with(
+        &self,
+        step: &str,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        success_codes: &[i32],
+    ) -> Result<()> {
+        let log_path = self.step_log_path(step);
+        let mut log = self.open(step)?;
+
+        writeln!(log, "$ {:?}", command)?;
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log.try_clone()?)
+            .spawn()
+            .with_context(|| format!("Running {:?}", command.get_program()))?;
+
+        let status = match timeout {
+            Some(timeout) => match child.wait_timeout(timeout)? {
+                Some(status) => status,
+                None => {
+                    child.kill()?;
+                    child.wait()?;
+                    writeln!(log, "[timed out]\n")?;
+
+                    bail!(
+                        "{:?} didn't finish within {} seconds, output is in {}",
+                        command.get_program(),
+                        timeout.as_secs(),
+                        log_path.display()
+                    );
+                }
+            },
+            None => child.wait()?,
+        };
+
+        if !status
+            .code()
+            .is_some_and(|code| success_codes.contains(&code))
+        {
+            let tail = output_tail(&log_path)?;
+            writeln!(log, "[{}]\n", status)?;
+
+            bail!(
+                "{:?} failed with {}, last lines of output:\n{}\nFull output is in {}",
+                command.get_program(),
+                status,
+                tail,
+                log_path.display()
+            );
+        }
+
+        writeln!(log, "[{}]\n", status)?;
+
+        Ok(())
+    }
+}
+
+/// The last `OUTPUT_TAIL_LINES` lines of the log at `log_path`
+fn output_tail(log_path: &Path) -> Result<String> {
+    let output =
+        fs::read(log_path).with_context(|| format!("Reading log file {}", log_path.display()))?;
+    let output = String::from_utf8_lossy(&output);
+
+    let lines: Vec<&str> = output.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..];
+
+    Ok(tail.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn failures_show_the_end_of_the_output() {
+        let dir = TempDir::new().unwrap();
+        let log = BuildLog::create(dir.path().to_path_buf()).unwrap();
+
+        let script = "for i in $(seq 1 30); do echo line $i; done; exit 3";
+        let error = log.run("programs", &mut shell(script)).unwrap_err();
+        let error = format!("{:#}", error);
+        assert!(error.contains("failed with exit status: 3"), "{}", error);
+        assert!(error.contains("line 11\nline 12"), "{}", error);
+        assert!(error.contains("line 30"), "{}", error);
+        assert!(!error.contains("line 10\n"), "{}", error);
+
+        log.run_with("programs", &mut shell(script), None, &[0, 3])
+            .unwrap();
+    }
+
+    #[test]
+    fn commands_that_take_too_long_are_killed() {
+        let dir = TempDir::new().unwrap();
+        let log = BuildLog::create(dir.path().to_path_buf()).unwrap();
+
+        let started = Instant::now();
+        let error = log
+            .run_with(
+                "programs",
+                &mut shell("sleep 10"),
+                Some(Duration::from_secs(1)),
+                &[0],
+            )
+            .unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(
+            error.to_string().contains("didn't finish within 1 seconds"),
+            "{}",
+            error
+        );
+        let output = fs::read_to_string(log.step_log_path("programs")).unwrap();
+        assert!(output.contains("[timed out]"), "{}", output);
+    }
+
+    #[test]
+    fn only_steps_that_run_again_lose_their_logs() {
+        let dir = TempDir::new().unwrap();
+        let log = BuildLog::create(dir.path().to_path_buf()).unwrap();
+        log.run("wineprefix", &mut shell("echo first wineprefix"))
+            .unwrap();
+        log.run("programs", &mut shell("echo first programs"))
+            .unwrap();
+
+        // The next build reuses the wineprefix and runs the programs twice
+        let log = BuildLog::create(dir.path().to_path_buf()).unwrap();
+        log.run("programs", &mut shell("echo second programs"))
+            .unwrap();
+        log.run("programs", &mut shell("echo third programs"))
+            .unwrap();
+
+        let read = |step| fs::read_to_string(log.step_log_path(step)).unwrap();
+        assert!(read("wineprefix").contains("first wineprefix"));
+        let programs = read("programs");
+        assert!(!programs.contains("first"), "{}", programs);
+        assert!(programs.contains("second") && programs.contains("third"));
+    }
+}
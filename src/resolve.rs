@@ -0,0 +1,824 @@
+use crate::validate::{self, Diagnostic};
+use crate::variables;
+
+use anyhow::{bail, Context, Error, Result};
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// Tables that replace the one they override as a whole instead of being merged into
+/// it, because their keys only make sense together
+const REPLACED_TABLES: &[&str] = &["wine.source", "wine.prefix.compress_wineprefix"];
+
+/// The key of a profile that's added to the name of the app and its bundle identifier.
+/// It's the name of the profile unless the profile says otherwise.
+const SUFFIX_KEY: &str = "suffix";
+
+/// Look at config files
+#[derive(Parser)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    subcmd: ConfigSubcommand,
+}
+
+#[derive(Subcommand)]
+enum ConfigSubcommand {
+    /// Print a config file
+    Show {
+        /// Path to config file
+        #[clap(short, long, default_value = "app.boxwine.toml")]
+        file: String,
+
+        /// Print the config the file makes together with the files it extends and
+        /// includes, with its variables filled in and the file each value comes from
+        #[clap(long)]
+        resolved: bool,
+
+        /// With --resolved, apply the overrides of [profile.<name>]
+        #[clap(long, requires = "resolved")]
+        profile: Option<String>,
+    },
+}
+
+pub fn config(opts: ConfigCommand) -> Result<()> {
+    match opts.subcmd {
+        ConfigSubcommand::Show {
+            file,
+            resolved,
+            profile,
+        } => {
+            let output = if resolved {
+                let mut resolved = resolve(&file)?;
+                resolved.select_profile(profile.as_deref())?;
+                let diagnostics = variables::fill_in(&mut resolved, None);
+                if !diagnostics.is_empty() {
+                    return Err(validate::report(&file, &diagnostics));
+                }
+                let files: Vec<&str> = resolved.files.iter().map(|f| f.path.as_str()).collect();
+
+                let mut output = format!("# Resolved from {}\n", files.join(", "));
+                write_table(&mut output, &resolved.table, "", Some(&resolved.origins));
+                output
+            } else {
+                let file = SourceFile::read(&file)?;
+                let mut output = String::new();
+                write_table(&mut output, &file.parse()?, "", None);
+                output
+            };
+            print!("{}", output.trim_start());
+
+            Ok(())
+        }
+    }
+}
+
+/// A config file as it was written
+pub struct SourceFile {
+    pub path: String,
+    pub contents: String,
+}
+
+impl SourceFile {
+    fn read(path: &str) -> Result<SourceFile> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Reading config file {}", path))?;
+
+        Ok(SourceFile {
+            path: path.to_string(),
+            contents,
+        })
+    }
+
+    fn parse(&self) -> Result<Table> {
+        toml::from_str(&self.contents).map_err(|e| {
+            let diagnostic = Diagnostic::from_parse_error(&e, self);
+            validate::report(&self.path, &[diagnostic])
+        })
+    }
+}
+
+/// A config put together from a file and every file it extends and includes
+pub struct Resolved {
+    /// The config file that was resolved
+    pub path: String,
+
+    /// Every file that went into the config, in the order they were merged
+    pub files: Vec<SourceFile>,
+
+    pub table: Table,
+
+    /// Where each value came from, by its key path like `wine.build.version`. Entries
+    /// of arrays of tables have their index in the path, like `wine.volume.0.from`.
+    pub origins: BTreeMap<String, Origin>,
+
+    /// What the selected profile adds to the name of the app and its bundle identifier
+    pub suffix: Option<String>,
+}
+
+/// Where a value in a resolved config was written
+#[derive(Clone, Default)]
+pub struct Origin {
+    /// The config file it's in
+    pub file: String,
+
+    /// Its key path in that file, which is different from the one in the resolved config
+    /// for profiles and appended entries of arrays of tables
+    pub path: String,
+}
+
+impl Resolved {
+    /// Apply the overrides of `[profile.<name>]` the same way a config overrides the
+    /// one it extends, and leave out every profile
+    pub fn select_profile(&mut self, name: Option<&str>) -> Result<()> {
+        let mut profiles = match self.table.remove("profile") {
+            None => Table::new(),
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => return Err(self.problem("profile", "profile is a table of profiles")),
+        };
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let mut profile = match profiles.remove(name) {
+            Some(Value::Table(profile)) => profile,
+            Some(_) => {
+                return Err(self.problem(
+                    &join_path("profile", name),
+                    &format!("profile.{} has to be a table", name),
+                ))
+            }
+            None => {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                match validate::closest(name, names.iter().copied()) {
+                    Some(suggestion) => {
+                        bail!("There's no profile {}, did you mean {}?", name, suggestion)
+                    }
+                    None if names.is_empty() => {
+                        bail!("There's no profile {}, the config has none", name)
+                    }
+                    None => bail!(
+                        "There's no profile {}, the config has {}",
+                        name,
+                        names.join(", ")
+                    ),
+                }
+            }
+        };
+
+        let suffix_path = join_path(&join_path("profile", name), SUFFIX_KEY);
+        let suffix = match profile.remove(SUFFIX_KEY) {
+            None => name.to_string(),
+            Some(Value::String(suffix)) => suffix,
+            Some(_) => {
+                return Err(self.problem(
+                    &suffix_path,
+                    &format!("profile.{}.suffix has to be a string", name),
+                ))
+            }
+        };
+        // The suffix goes into the bundle identifier, which can only have these in it
+        if !suffix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(self.problem(
+                &suffix_path,
+                &format!(
+                    "The suffix {} can only have letters, digits and - in it",
+                    suffix
+                ),
+            ));
+        }
+
+        let profile_origins = self.origins.clone();
+        let origin_of = |path: &str| origin(&profile_origins, path).cloned().unwrap_or_default();
+        merge(
+            &mut self.table,
+            profile,
+            "",
+            &join_path("profile", name),
+            &origin_of,
+            &mut self.origins,
+        );
+
+        self.suffix = Some(suffix).filter(|suffix| !suffix.is_empty());
+
+        Ok(())
+    }
+
+    /// Everything wrong with a profile, at the key at `path`
+    fn problem(&self, path: &str, message: &str) -> Error {
+        validate::report(
+            &self.path,
+            &[Diagnostic::new(message.to_string()).at(&self.files, &self.origins, path)],
+        )
+    }
+}
+
+/// Put the config at `path` together. What a file extends comes first, then what it
+/// includes in order, then the file itself, and what comes later overrides what came
+/// before:
+///
+/// - tables are merged key by key, except for `REPLACED_TABLES`
+/// - arrays of tables like `[[wine.volume]]` and `[[wine.run]]` are appended
+/// - everything else, including arrays of values like `verbs`, is replaced
+///
+/// Paths to other files are relative to the file that names them. A file that comes up
+/// more than once is only merged the first time.
+pub fn resolve(path: &str) -> Result<Resolved> {
+    let mut resolved = Resolved {
+        path: path.to_string(),
+        files: Vec::new(),
+        table: Table::new(),
+        origins: BTreeMap::new(),
+        suffix: None,
+    };
+    let mut seen = Vec::new();
+    let mut stack = Vec::new();
+    resolve_file(Path::new(path), &mut resolved, &mut seen, &mut stack)?;
+
+    Ok(resolved)
+}
+
+fn resolve_file(
+    path: &Path,
+    resolved: &mut Resolved,
+    seen: &mut Vec<PathBuf>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let file = SourceFile::read(&path.to_string_lossy())?;
+    let canonical_path = path
+        .canonicalize()
+        .with_context(|| format!("Finding {}", path.display()))?;
+    if stack.contains(&canonical_path) {
+        bail!("{} extends or includes itself", path.display());
+    }
+    if seen.contains(&canonical_path) {
+        return Ok(());
+    }
+    seen.push(canonical_path.clone());
+
+    let mut table = file.parse()?;
+    let extends = table.remove("extends");
+    let include = table.remove("include");
+    let not_a_path = |key: &str, message: &str| {
+        let diagnostic = Diagnostic::new(message.to_string()).at_key(&file, key);
+        validate::report(&file.path, &[diagnostic])
+    };
+
+    let mut parents = Vec::new();
+    match extends {
+        None => {}
+        Some(Value::String(extends)) => parents.push(extends),
+        Some(_) => {
+            return Err(not_a_path(
+                "extends",
+                "extends is the path of a config file",
+            ))
+        }
+    }
+    match include {
+        None => {}
+        Some(Value::Array(include)) => {
+            for included in include {
+                match included {
+                    Value::String(included) => parents.push(included),
+                    _ => return Err(not_a_path("include", "include is a list of config files")),
+                }
+            }
+        }
+        Some(_) => return Err(not_a_path("include", "include is a list of config files")),
+    }
+
+    stack.push(canonical_path);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for parent in parents {
+        resolve_file(&dir.join(&parent), resolved, seen, stack).with_context(|| {
+            format!(
+                "Reading {}, which {} extends or includes",
+                parent, file.path
+            )
+        })?;
+    }
+    stack.pop();
+
+    let origin_of = |layer_path: &str| Origin {
+        file: file.path.clone(),
+        path: layer_path.to_string(),
+    };
+    merge(
+        &mut resolved.table,
+        table,
+        "",
+        "",
+        &origin_of,
+        &mut resolved.origins,
+    );
+    resolved.files.push(file);
+
+    Ok(())
+}
+
+/// Merge `layer` into `base`, the table at `path`. `layer_path` is where `layer` is in
+/// the config it comes from, `origin_of` says where a value there came from.
+fn merge(
+    base: &mut Table,
+    layer: Table,
+    path: &str,
+    layer_path: &str,
+    origin_of: &dyn Fn(&str) -> Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    for (key, value) in layer {
+        let key_path = join_path(path, &key);
+        let layer_key_path = join_path(layer_path, &key);
+
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(table))
+                if !REPLACED_TABLES.contains(&key_path.as_str()) =>
+            {
+                merge(
+                    base_table,
+                    table,
+                    &key_path,
+                    &layer_key_path,
+                    origin_of,
+                    origins,
+                );
+            }
+            (Some(Value::Array(base_array)), Value::Array(array))
+                if is_array_of_tables(base_array) && is_array_of_tables(&array) =>
+            {
+                for (index, entry) in array.iter().enumerate() {
+                    let entry_path = join_path(&key_path, &(base_array.len() + index).to_string());
+                    let layer_entry_path = join_path(&layer_key_path, &index.to_string());
+                    record_origins(entry, &entry_path, &layer_entry_path, origin_of, origins);
+                }
+                base_array.extend(array);
+            }
+            (_, value) => {
+                let nested_path = format!("{}.", key_path);
+                origins.retain(|path, _| *path != key_path && !path.starts_with(&nested_path));
+                record_origins(&value, &key_path, &layer_key_path, origin_of, origins);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Remember where `value` at `path` and everything in it came from
+fn record_origins(
+    value: &Value,
+    path: &str,
+    layer_path: &str,
+    origin_of: &dyn Fn(&str) -> Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record_origins(
+                    value,
+                    &join_path(path, key),
+                    &join_path(layer_path, key),
+                    origin_of,
+                    origins,
+                );
+            }
+        }
+        Value::Array(array) if is_array_of_tables(array) => {
+            for (index, entry) in array.iter().enumerate() {
+                let index = index.to_string();
+                record_origins(
+                    entry,
+                    &join_path(path, &index),
+                    &join_path(layer_path, &index),
+                    origin_of,
+                    origins,
+                );
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), origin_of(layer_path));
+        }
+    }
+}
+
+pub fn is_array_of_tables(array: &[Value]) -> bool {
+    !array.is_empty() && array.iter().all(Value::is_table)
+}
+
+pub fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Write `table` at `path` as TOML, with the file each value comes from after it when
+/// there are `origins`. Values come before tables like TOML needs them to.
+fn write_table(
+    output: &mut String,
+    table: &Table,
+    path: &str,
+    origins: Option<&BTreeMap<String, Origin>>,
+) {
+    let (plain, nested): (Vec<_>, Vec<_>) = table.iter().partition(|(_, value)| match value {
+        Value::Table(_) => false,
+        Value::Array(array) => !is_array_of_tables(array),
+        _ => true,
+    });
+
+    for (key, value) in plain {
+        output.push_str(&format!("{} = {}", format_key(key), format_value(value)));
+        if let Some(origin) = origins.and_then(|origins| origin(origins, &join_path(path, key))) {
+            output.push_str(&format!("  # {}", origin.file));
+        }
+        output.push('\n');
+    }
+
+    for (key, value) in nested {
+        let key_path = join_path(path, key);
+        let header = if path.is_empty() {
+            format_key(key)
+        } else {
+            format!("{}.{}", path, format_key(key))
+        };
+
+        match value {
+            Value::Table(table) => {
+                // Tables with nothing but tables in them don't need a header of their own
+                let only_tables = !table.is_empty()
+                    && table.values().all(|value| match value {
+                        Value::Table(_) => true,
+                        Value::Array(array) => is_array_of_tables(array),
+                        _ => false,
+                    });
+                if !only_tables {
+                    output.push_str(&format!("\n[{}]\n", header));
+                }
+                write_table(output, table, &key_path, origins);
+            }
+            Value::Array(array) => {
+                for (index, entry) in array.iter().enumerate() {
+                    output.push_str(&format!("\n[[{}]]\n", header));
+                    if let Value::Table(entry) = entry {
+                        let entry_path = join_path(&key_path, &index.to_string());
+                        write_entry(output, entry, &entry_path, origins);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Write an entry of an array of tables, with its tables inline. The origins have the
+/// index of the entry in their paths, the headers don't.
+fn write_entry(
+    output: &mut String,
+    entry: &Table,
+    entry_path: &str,
+    origins: Option<&BTreeMap<String, Origin>>,
+) {
+    for (key, value) in entry {
+        output.push_str(&format!("{} = {}", format_key(key), format_value(value)));
+        if let Some(origin) =
+            origins.and_then(|origins| origin(origins, &join_path(entry_path, key)))
+        {
+            output.push_str(&format!("  # {}", origin.file));
+        }
+        output.push('\n');
+    }
+}
+
+/// Where the value at `path` came from. Tables come from where the first of their
+/// values did.
+pub fn origin<'a>(origins: &'a BTreeMap<String, Origin>, path: &str) -> Option<&'a Origin> {
+    nested_origin(origins, path).map(|(_, origin)| origin)
+}
+
+/// The key path of the value at `path` or the first value in it, and where that came from
+pub fn nested_origin<'a>(
+    origins: &'a BTreeMap<String, Origin>,
+    path: &str,
+) -> Option<(&'a str, &'a Origin)> {
+    let nested_path = format!("{}.", path);
+
+    origins
+        .get_key_value(path)
+        .or_else(|| {
+            origins
+                .range(nested_path.clone()..)
+                .next()
+                .filter(|(key, _)| key.starts_with(&nested_path))
+        })
+        .map(|(key, origin)| (key.as_str(), origin))
+}
+
+/// A key as it's written in TOML, quoted unless it's a bare key
+fn format_key(key: &str) -> String {
+    let is_bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if is_bare {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// A value as it's written in TOML on one line, with tables written inline
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", format_key(key), format_value(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Value::Array(array) => {
+            let entries: Vec<String> = array.iter().map(format_value).collect();
+            format!("[{}]", entries.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Write `files` into a new directory, the first one is the config to resolve
+    fn write_files(files: &[(&str, &str)]) -> (TempDir, String) {
+        let dir = TempDir::new().unwrap();
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let path = dir.path().join(files[0].0).to_string_lossy().into_owned();
+        (dir, path)
+    }
+
+    fn get<'a>(table: &'a Table, path: &str) -> Option<&'a Value> {
+        let mut parts = path.split('.');
+        let mut value = table.get(parts.next()?)?;
+        for part in parts {
+            value = match value {
+                Value::Array(array) => array.get(part.parse::<usize>().ok()?)?,
+                value => value.get(part)?,
+            };
+        }
+        Some(value)
+    }
+
+    fn programs(table: &Table) -> Vec<&str> {
+        get(table, "wine.run")
+            .and_then(Value::as_array)
+            .map(|runs| {
+                runs.iter()
+                    .map(|run| run["program"].as_str().unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    const BASE: &str = r#"
+[wine.source]
+type = "url"
+url = "https://example.com/wine.tar.xz"
+
+[wine.build]
+branch = "staging"
+version = "5.0"
+
+[wine.prefix.compress_wineprefix]
+format = "zstd"
+level = 19
+
+[[wine.run]]
+program = "base.exe"
+
+[winetricks]
+verbs = ["a", "b"]
+"#;
+
+    const APP: &str = r#"
+extends = "base.boxwine.toml"
+
+[wine.source]
+type = "winehq"
+
+[wine.build]
+version = "6.0"
+
+[wine.prefix.compress_wineprefix]
+format = "xz"
+
+[[wine.run]]
+program = "app.exe"
+
+[winetricks]
+verbs = ["c"]
+"#;
+
+    #[test]
+    fn merges_tables_appends_arrays_of_tables_and_replaces_the_rest() {
+        let (_dir, path) = write_files(&[("app.boxwine.toml", APP), ("base.boxwine.toml", BASE)]);
+        let resolved = resolve(&path).unwrap();
+        let table = &resolved.table;
+
+        // Merged key by key
+        assert_eq!(
+            get(table, "wine.build.branch").unwrap().as_str(),
+            Some("staging")
+        );
+        assert_eq!(
+            get(table, "wine.build.version").unwrap().as_str(),
+            Some("6.0")
+        );
+        // Replaced as a whole
+        assert!(get(table, "wine.source.url").is_none());
+        assert!(get(table, "wine.prefix.compress_wineprefix.level").is_none());
+        assert_eq!(
+            get(table, "winetricks.verbs"),
+            Some(&Value::Array(vec![Value::String("c".to_string())]))
+        );
+        // Appended
+        assert_eq!(programs(table), ["base.exe", "app.exe"]);
+
+        // The appended entry is the first one in the file it came from
+        let origin = &resolved.origins["wine.run.1.program"];
+        assert_eq!(origin.file, path);
+        assert_eq!(origin.path, "wine.run.0.program");
+        assert!(resolved.origins["wine.build.branch"]
+            .file
+            .ends_with("base.boxwine.toml"));
+        assert!(!resolved.origins.contains_key("wine.source.url"));
+    }
+
+    #[test]
+    fn merges_a_file_included_twice_once() {
+        let (_dir, path) = write_files(&[
+            (
+                "app.boxwine.toml",
+                r#"include = ["left.boxwine.toml", "right.boxwine.toml"]"#,
+            ),
+            (
+                "left.boxwine.toml",
+                "extends = \"common.boxwine.toml\"\n[[wine.run]]\nprogram = \"left.exe\"\n",
+            ),
+            (
+                "right.boxwine.toml",
+                "extends = \"common.boxwine.toml\"\n[[wine.run]]\nprogram = \"right.exe\"\n",
+            ),
+            (
+                "common.boxwine.toml",
+                "[[wine.run]]\nprogram = \"common.exe\"\n",
+            ),
+        ]);
+        let resolved = resolve(&path).unwrap();
+
+        assert_eq!(
+            programs(&resolved.table),
+            ["common.exe", "left.exe", "right.exe"]
+        );
+        let files: Vec<&str> = resolved
+            .files
+            .iter()
+            .map(|file| Path::new(&file.path).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "common.boxwine.toml",
+                "left.boxwine.toml",
+                "right.boxwine.toml",
+                "app.boxwine.toml"
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_configs_that_extend_themselves() {
+        let (_dir, path) = write_files(&[
+            ("a.boxwine.toml", "extends = \"b.boxwine.toml\"\n"),
+            ("b.boxwine.toml", "include = [\"a.boxwine.toml\"]\n"),
+        ]);
+
+        let error = format!("{:#}", resolve(&path).err().unwrap());
+        assert!(
+            error.contains("a.boxwine.toml extends or includes itself"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn shows_the_file_every_value_comes_from() {
+        let (_dir, path) = write_files(&[("app.boxwine.toml", APP), ("base.boxwine.toml", BASE)]);
+        let resolved = resolve(&path).unwrap();
+        let base_path = Path::new(&path).with_file_name("base.boxwine.toml");
+
+        let mut output = String::new();
+        write_table(&mut output, &resolved.table, "", Some(&resolved.origins));
+
+        let lines: Vec<&str> = output.lines().collect();
+        let app_origin = format!("  # {}", path);
+        let base_origin = format!("  # {}", base_path.display());
+        assert!(
+            lines.contains(&format!("version = \"6.0\"{}", app_origin).as_str()),
+            "{}",
+            output
+        );
+        assert!(
+            lines.contains(&format!("branch = \"staging\"{}", base_origin).as_str()),
+            "{}",
+            output
+        );
+        assert!(
+            lines.contains(&format!("program = \"base.exe\"{}", base_origin).as_str()),
+            "{}",
+            output
+        );
+        assert!(
+            lines.contains(&format!("program = \"app.exe\"{}", app_origin).as_str()),
+            "{}",
+            output
+        );
+        assert_eq!(
+            lines.iter().filter(|line| **line == "[[wine.run]]").count(),
+            2
+        );
+    }
+
+    const PROFILES: &str = r#"
+[app]
+identifier = "com.example.game"
+
+[app.entrypoint]
+program = "c:/game.exe"
+
+[profile.debug.app.entrypoint.env]
+WINEDEBUG = "err+all"
+
+[profile.release]
+suffix = ""
+"#;
+
+    #[test]
+    fn profiles_add_their_suffix_to_the_bundle_identifier() {
+        let (_dir, path) = write_files(&[("app.boxwine.toml", PROFILES)]);
+        let identifier = |profile| {
+            let mut resolved = resolve(&path).unwrap();
+            resolved.select_profile(profile).unwrap();
+            let config = crate::config::from_resolved(resolved, None).unwrap();
+            config.get_bundle_identifier()
+        };
+
+        assert_eq!(identifier(None), "com.example.game");
+        assert_eq!(identifier(Some("debug")), "com.example.game.debug");
+        assert_eq!(identifier(Some("release")), "com.example.game");
+
+        let mut resolved = resolve(&path).unwrap();
+        resolved.select_profile(Some("debug")).unwrap();
+        assert_eq!(resolved.suffix.as_deref(), Some("debug"));
+        assert!(get(&resolved.table, "profile").is_none());
+        assert_eq!(
+            get(&resolved.table, "app.entrypoint.env.WINEDEBUG")
+                .unwrap()
+                .as_str(),
+            Some("err+all")
+        );
+        let origin = &resolved.origins["app.entrypoint.env.WINEDEBUG"];
+        assert_eq!(origin.path, "profile.debug.app.entrypoint.env.WINEDEBUG");
+    }
+
+    #[test]
+    fn refuses_unknown_profiles() {
+        let (_dir, path) = write_files(&[("app.boxwine.toml", PROFILES)]);
+        let error = |profile| {
+            let mut resolved = resolve(&path).unwrap();
+            resolved
+                .select_profile(Some(profile))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("debgu"),
+            "There's no profile debgu, did you mean debug?"
+        );
+        assert_eq!(
+            error("beta"),
+            "There's no profile beta, the config has debug, release"
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use crate::config;
+use crate::resolve::{self, Origin, SourceFile};
+
+use anyhow::{anyhow, Error, Result};
+use clap::Parser;
+use std::collections::{BTreeMap, HashMap};
+
+/// How far off an unknown key or value can be from a known one to be suggested
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+/// Check a config file for mistakes without building anything
+#[derive(Parser)]
+pub struct Validate {
+    /// Path to config file
+    #[clap(short, long, default_value = "app.boxwine.toml")]
+    file: String,
+
+    /// Check the config with the overrides of [profile.<name>]
+    #[clap(long)]
+    profile: Option<String>,
+}
+
+pub fn validate(opts: Validate) -> Result<()> {
+    config::load(&opts.file, opts.profile.as_deref(), None)?;
+    println!("{} is valid", opts.file);
+
+    Ok(())
+}
+
+/// A mistake in a config file, and where it is if that's known
+pub struct Diagnostic {
+    message: String,
+
+    /// The file, and zero-based line and column
+    location: Option<(String, usize, usize)>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String) -> Diagnostic {
+        Diagnostic {
+            message,
+            location: None,
+        }
+    }
+
+    /// The diagnostic for a config file that isn't valid TOML, at the position the
+    /// parser gives
+    pub fn from_parse_error(error: &toml::de::Error, parsed: &SourceFile) -> Diagnostic {
+        Diagnostic {
+            message: parse_error_message(error),
+            location: error
+                .line_col()
+                .map(|(line, column)| (parsed.path.clone(), line, column)),
+        }
+    }
+
+    /// The diagnostic for a resolved config that doesn't fit what a config can be,
+    /// with a suggestion for keys and values that are almost right. `contents` is the
+    /// resolved config that was parsed, the key its error is at is looked for in
+    /// `files` through `origins`.
+    pub fn from_resolved_parse_error(
+        error: &toml::de::Error,
+        contents: &str,
+        files: &[SourceFile],
+        origins: &BTreeMap<String, Origin>,
+    ) -> Diagnostic {
+        let diagnostic = Diagnostic::new(parse_error_message(error));
+
+        match error_path(error, contents) {
+            Some(path) => diagnostic.at(files, origins, &path),
+            None => diagnostic,
+        }
+    }
+
+    /// Point the diagnostic at the key at `path` in a resolved config, like
+    /// `wine.run.1.timeout`, in the file it was written in. A key that isn't written
+    /// anywhere, like one that was left out, points at the table it would be in.
+    pub fn at(
+        mut self,
+        files: &[SourceFile],
+        origins: &BTreeMap<String, Origin>,
+        path: &str,
+    ) -> Diagnostic {
+        let mut path = path;
+
+        self.location = loop {
+            let location = resolve::nested_origin(origins, path).and_then(|(key, origin)| {
+                // The origin can be of a value in the table, its path in the file ends
+                // the same way
+                let file_path = origin.path.strip_suffix(&key[path.len()..])?;
+                let file = files.iter().find(|file| file.path == origin.file)?;
+                let (line, column) = key_location(&file.contents, file_path)?;
+                Some((file.path.clone(), line, column))
+            });
+
+            match (location, path.rsplit_once('.')) {
+                (Some(location), _) => break Some(location),
+                (None, Some((table, _))) => path = table,
+                (None, None) => break None,
+            }
+        };
+
+        self
+    }
+
+    /// Point the diagnostic at the key at `path` in `file`, like `extends`
+    pub fn at_key(mut self, file: &SourceFile, path: &str) -> Diagnostic {
+        self.location = key_location(&file.contents, path)
+            .map(|(line, column)| (file.path.clone(), line, column));
+
+        self
+    }
+}
+
+/// One error listing every diagnostic, each prefixed with `file:line:column`. Those
+/// that can't be placed are about `file`.
+pub fn report(file: &str, diagnostics: &[Diagnostic]) -> Error {
+    let lines: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| match &diagnostic.location {
+            Some((file, line, column)) => {
+                format!(
+                    "{}:{}:{}: {}",
+                    file,
+                    line + 1,
+                    column + 1,
+                    diagnostic.message
+                )
+            }
+            None => format!("{}: {}", file, diagnostic.message),
+        })
+        .collect();
+
+    anyhow!("{}", lines.join("\n"))
+}
+
+/// The message of a parse error, with a suggestion for keys and values that are almost
+/// right
+fn parse_error_message(error: &toml::de::Error) -> String {
+    // The position is shown in front of the message instead
+    let mut message = error.to_string();
+    if let Some(position) = message.find(" at line ") {
+        message.truncate(position);
+    }
+
+    // serde lists every key or value there could have been, just the closest one is
+    // easier to read
+    if let Some((unknown, suggestion)) = suggestion(&message) {
+        message = match message.split_once(" for key ") {
+            Some((_, key)) => format!(
+                "{} for key {}, did you mean `{}`?",
+                unknown, key, suggestion
+            ),
+            None => format!("{}, did you mean `{}`?", unknown, suggestion),
+        };
+    }
+    if message.contains("untagged enum CompressWineprefix") {
+        message.push_str(
+            r#", compress_wineprefix is true, false, "none", "gzip", "xz", "zstd" or { format = "zstd", level = 19 }"#,
+        );
+    } else if message.contains("untagged enum Persist") {
+        message.push_str(", every app.persist needs either a path or a registry key");
+    }
+
+    message
+}
+
+/// The path of the key a parse error of `contents` is about, like `wine.run.1.timeout`.
+/// The key path in the message leaves out which entry of an array of tables it's in,
+/// so it's taken from the line the parser points at instead: the key for a bad value,
+/// the table an unknown field is in.
+fn error_path(error: &toml::de::Error, contents: &str) -> Option<String> {
+    let (error_line, _) = error.line_col()?;
+    let message = error.to_string();
+    let field = match message.starts_with("unknown field ") {
+        true => message.split('`').nth(1),
+        false => None,
+    };
+
+    let mut path = walk_keys(contents, |line, _, key, _| {
+        (line == error_line).then(|| key.to_vec())
+    })
+    .unwrap_or_default();
+    if let Some(field) = field {
+        if path.last().map(String::as_str) != Some(field) {
+            path.push(field.to_string());
+        }
+    }
+
+    match path.is_empty() {
+        true => None,
+        false => Some(path.join(".")),
+    }
+}
+
+/// Zero-based line and column of the key at `path`, like `wine.run.1.timeout`, where
+/// entries of arrays of tables are counted by their `[[table]]` headers. A key in an
+/// inline table points at that key on the line of the inline table.
+fn key_location(contents: &str, path: &str) -> Option<(usize, usize)> {
+    walk_keys(contents, |line, content, key, is_header| {
+        let trimmed = content.trim_start();
+        let column = content.len() - trimmed.len();
+
+        let key = key.join(".");
+        if key == path {
+            return Some((line, column));
+        }
+        let inline_key = path
+            .strip_prefix(&key)
+            .and_then(|rest| rest.strip_prefix('.'));
+        if let (false, Some(inline_key)) = (is_header, inline_key) {
+            let name = inline_key.rsplit('.').next().unwrap_or(inline_key);
+            let inline_column = trimmed
+                .find('=')
+                .and_then(|equals| Some(equals + trimmed[equals..].find(name)?))
+                .map_or(0, |inline_column| trimmed[..inline_column].chars().count());
+            return Some((line, column + inline_column));
+        }
+
+        None
+    })
+}
+
+/// Call `visit` with the zero-based line, the line itself and the full key path of
+/// every key and table header in `contents`, until it returns something. Entries of
+/// arrays of tables are counted by their `[[table]]` headers.
+fn walk_keys<T>(
+    contents: &str,
+    mut visit: impl FnMut(usize, &str, &[String], bool) -> Option<T>,
+) -> Option<T> {
+    let mut current_table = Vec::new();
+    // How many entries each array of tables has had so far
+    let mut array_lengths: HashMap<String, usize> = HashMap::new();
+
+    for (line, content) in contents.lines().enumerate() {
+        let trimmed = content.trim_start();
+
+        let (key, is_header) = if let Some(header) = trimmed.strip_prefix("[[") {
+            let mut key = match parse_key(header) {
+                Some((key, rest)) if rest.starts_with("]]") => key,
+                _ => continue,
+            };
+            // A new entry of the array, in the last entry of the arrays it's in
+            let name = key.pop()?;
+            let mut table = indexed_path(&key, &array_lengths);
+            table.push(name);
+            let length = array_lengths.entry(table.join(".")).or_insert(0);
+            current_table = table;
+            current_table.push(length.to_string());
+            *length += 1;
+            (current_table.clone(), true)
+        } else if let Some(header) = trimmed.strip_prefix('[') {
+            match parse_key(header) {
+                Some((key, rest)) if rest.starts_with(']') => {
+                    current_table = indexed_path(&key, &array_lengths);
+                    (current_table.clone(), true)
+                }
+                _ => continue,
+            }
+        } else {
+            // Comments, values that go on over more lines and anything else that isn't
+            // a key don't have an = after what could be a key
+            match parse_key(trimmed) {
+                Some((key, rest)) if rest.starts_with('=') && !rest.starts_with("==") => {
+                    let mut full_key = current_table.clone();
+                    full_key.extend(key);
+                    (full_key, false)
+                }
+                _ => continue,
+            }
+        };
+
+        if let Some(found) = visit(line, content, &key, is_header) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// `key` with the index of the last entry after every part of it that's an array of
+/// tables, because that's the entry `[key]` and `[[key]]` headers go into
+fn indexed_path(key: &[String], array_lengths: &HashMap<String, usize>) -> Vec<String> {
+    let mut path = Vec::new();
+
+    for part in key {
+        path.push(part.clone());
+        if let Some(length) = array_lengths.get(&path.join(".")) {
+            path.push(length.saturating_sub(1).to_string());
+        }
+    }
+
+    path
+}
+
+/// The parts of a dotted TOML key at the start of `text`, bare or quoted, and what comes
+/// after it
+fn parse_key(text: &str) -> Option<(Vec<String>, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim_start();
+
+    loop {
+        let (part, after) = match rest.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = rest[1..].find(quote)? + 1;
+                (&rest[1..end], &rest[end + 1..])
+            }
+            _ => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                    .unwrap_or(rest.len());
+                if end == 0 {
+                    return None;
+                }
+                rest.split_at(end)
+            }
+        };
+        parts.push(part.to_string());
+
+        rest = after.trim_start();
+        match rest.strip_prefix('.') {
+            Some(after_dot) => rest = after_dot.trim_start(),
+            None => return Some((parts, rest)),
+        }
+    }
+}
+
+/// The part of serde's "unknown field `x`, expected one of `a`, `b`" that says what
+/// was unknown, and the closest of the expected keys or values to it
+fn suggestion(message: &str) -> Option<(&str, &str)> {
+    if !message.starts_with("unknown field ") && !message.starts_with("unknown variant ") {
+        return None;
+    }
+    let (unknown, expected) = message.split_once(", expected ")?;
+    let expected = expected.split(" for key ").next()?;
+    let name = unknown.split('`').nth(1)?;
+
+    let suggestion = closest(name, expected.split('`').skip(1).step_by(2))?;
+
+    Some((unknown, suggestion))
+}
+
+/// The one of `candidates` that's closest to `name`, if any is close enough to be what
+/// was meant
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# arch = "64" in a comment
+[wine.build]
+arch = "64"  # timeout = 0
+
+[[wine.run]]
+program = "c:/a.exe"
+
+[[wine.run]]
+program = "c:/b.exe"
+timeout = 0
+env = { LANG = "en_US.UTF-8" }
+
+[wine.run.extra]
+"quoted key" = 1
+
+[profile.debug]
+wine.prefix.sandbox = false
+"#;
+
+    #[test]
+    fn finds_keys_and_skips_comments() {
+        assert_eq!(key_location(CONFIG, "wine.build.arch"), Some((2, 0)));
+        assert_eq!(key_location(CONFIG, "wine.build"), Some((1, 0)));
+        assert_eq!(key_location(CONFIG, "wine.run.1.timeout"), Some((9, 0)));
+        assert_eq!(key_location(CONFIG, "wine.run.0.timeout"), None);
+        assert_eq!(key_location(CONFIG, "wine.run.1.env.LANG"), Some((10, 8)));
+        assert_eq!(
+            key_location(CONFIG, "wine.run.1.extra.quoted key"),
+            Some((13, 0))
+        );
+        assert_eq!(
+            key_location(CONFIG, "profile.debug.wine.prefix.sandbox"),
+            Some((16, 0))
+        );
+    }
+
+    /// The error `config::load` gives for `config`, which extends `base` if there is one
+    fn load_error(config: &str, base: Option<&str>) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.boxwine.toml");
+        std::fs::write(&path, config).unwrap();
+        if let Some(base) = base {
+            std::fs::write(dir.path().join("base.boxwine.toml"), base).unwrap();
+        }
+
+        let error = config::load(path.to_str().unwrap(), None, None).err().unwrap();
+        let error = error.to_string();
+        error
+            .strip_prefix(&format!("{}:", path.display()))
+            .unwrap_or(&error)
+            .to_string()
+    }
+
+    const ENTRYPOINT: &str = r#"
+[app.entrypoint]
+program = "c:/app.exe"
+"#;
+
+    #[test]
+    fn places_parse_errors_in_arrays_of_tables() {
+        let config = format!(
+            r#"{}
+[[wine.run]]
+program = "c:/a.exe"
+timeout = 10
+
+[[wine.run]]
+program = "c:/b.exe"
+timeout = "x"
+"#,
+            ENTRYPOINT
+        );
+        let error = load_error(&config, None);
+        assert!(error.starts_with("11:1: invalid type"), "{}", error);
+
+        let config = format!(
+            r#"{}
+[[wine.run]]
+program = "c:/a.exe"
+
+[[wine.run]]
+program = "c:/b.exe"
+  timeoutt = 3
+"#,
+            ENTRYPOINT
+        );
+        let error = load_error(&config, None);
+        assert!(error.starts_with("10:3: unknown field `timeoutt`"), "{}", error);
+        assert!(error.ends_with("did you mean `timeout`?"), "{}", error);
+    }
+
+    #[test]
+    fn places_parse_errors_at_dotted_keys() {
+        let config = format!("{}\n[wine]\nprefix.install_gecko = \"x\"\n", ENTRYPOINT);
+        let error = load_error(&config, None);
+        assert!(error.starts_with("6:1: invalid type"), "{}", error);
+    }
+
+    #[test]
+    fn places_parse_errors_in_the_file_they_come_from() {
+        let config = format!("extends = \"base.boxwine.toml\"\n{}", ENTRYPOINT);
+        let base = "[[wine.run]]\nprogram = \"c:/a.exe\"\nwait = 1\n";
+        let error = load_error(&config, Some(base));
+        assert!(error.contains("base.boxwine.toml:3:1: invalid type"), "{}", error);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::config::Config;
+use crate::resolve::{self, Origin, Resolved};
+use crate::validate::{self, Diagnostic};
+
+use anyhow::{anyhow, bail, Result};
+use boxwine::interpolate::{interpolate, Expansion, DRIVE_C_VARIABLE, WINEPREFIX_VARIABLE};
+use boxwine::winpath::WindowsPath;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+const CONFIG_DIR_VARIABLE: &str = "CONFIG_DIR";
+const APP_NAME_VARIABLE: &str = "APP_NAME";
+
+/// Variables that come with boxwine, which `[vars]` can't replace
+const BUILT_IN_VARIABLES: &[&str] = &[
+    WINEPREFIX_VARIABLE,
+    DRIVE_C_VARIABLE,
+    CONFIG_DIR_VARIABLE,
+    APP_NAME_VARIABLE,
+];
+
+/// Where host environment variables are, like `${env:HOME}`
+const ENV_PREFIX: &str = "env:";
+
+/// The entrypoint runs on the user's Mac, so the launcher fills in the wineprefix there
+const ENTRYPOINT_PATH: &str = "app.entrypoint";
+
+/// Values that are paths on the host, which are relative to the config file they're in.
+/// `*` is any entry of an array of tables.
+const HOST_PATHS: &[&str] = &[
+    "app.icon",
+    "wine.source.path",
+    "wine.prefix.base_prefix",
+    "wine.volume.*.from",
+    "wine.run.*.program",
+    "wine.run.*.cwd",
+];
+
+/// Host paths that can also be the bare name of a Windows program wine finds by itself,
+/// like `regedit`
+const PROGRAM_PATHS: &[&str] = &["wine.run.*.program"];
+
+/// Fill in the variables in every string of a resolved config, and make the host paths
+/// in it absolute. The wineprefix is only known while building an app, without
+/// `wineprefix_path` its variables are left as they are.
+///
+/// Variables are written `${name}`, and `$${` is a literal `${`. There are:
+///
+/// - `${WINEPREFIX}` and `${DRIVE_C}`, where the wineprefix and its drive C: are on the
+///   host. In `app.entrypoint` they're where they are on the Mac the app runs on.
+/// - `${CONFIG_DIR}`, the directory of the config file the value is in
+/// - `${APP_NAME}`, the name of the app
+/// - `${env:NAME}`, the environment variable `NAME` on the host
+/// - every variable in the `[vars]` table, which can use other variables too
+pub fn fill_in(resolved: &mut Resolved, wineprefix_path: Option<&Path>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let vars = match resolved.table.remove("vars") {
+        Some(Value::Table(vars)) => vars,
+        Some(_) => {
+            diagnostics.push(
+                Diagnostic::new("vars is a table of variables".to_string()).at(
+                    &resolved.files,
+                    &resolved.origins,
+                    "vars",
+                ),
+            );
+            Table::new()
+        }
+        None => Table::new(),
+    };
+    for name in vars.keys() {
+        if BUILT_IN_VARIABLES.contains(&name.as_str()) {
+            diagnostics.push(
+                Diagnostic::new(format!("vars.{} would replace a built-in variable", name)).at(
+                    &resolved.files,
+                    &resolved.origins,
+                    &format!("vars.{}", name),
+                ),
+            );
+        }
+    }
+
+    let app_name = match resolved.table.get("app").and_then(|app| app.get("name")) {
+        Some(Value::String(name)) => name.clone(),
+        _ => Config::default().get_app_name().clone(),
+    };
+
+    let mut variables = Variables {
+        vars,
+        app_name,
+        origins: &resolved.origins,
+        wineprefix_path,
+        expanding: Vec::new(),
+    };
+    // A broken variable breaks every value that uses it, it's only reported once
+    let files = &resolved.files;
+    let origins = &resolved.origins;
+    let mut messages = Vec::new();
+    let mut problem = |e: anyhow::Error, path: &str| {
+        let message = format!("{:#}", e);
+        if !messages.contains(&message) {
+            messages.push(message.clone());
+            diagnostics.push(Diagnostic::new(message).at(files, origins, path));
+        }
+    };
+
+    // Variables are checked where they're defined first, even the ones nothing uses
+    let var_names: Vec<String> = variables.vars.keys().cloned().collect();
+    for name in var_names {
+        if let Err(e) = variables.expand(&name, Path::new(""), false) {
+            problem(e, &format!("vars.{}", name));
+        }
+    }
+
+    for (key, value) in resolved.table.iter_mut() {
+        fill_in_value(value, key, &mut variables, &mut problem);
+    }
+
+    diagnostics
+}
+
+/// Fill in `value` at `path` and everything in it
+fn fill_in_value(
+    value: &mut Value,
+    path: &str,
+    variables: &mut Variables,
+    problem: &mut impl FnMut(anyhow::Error, &str),
+) {
+    match value {
+        Value::String(text) => {
+            let deferred =
+                path == ENTRYPOINT_PATH || path.starts_with(&format!("{}.", ENTRYPOINT_PATH));
+            let config_dir = variables.config_dir(path);
+
+            match variables.fill_in(text, &config_dir, deferred) {
+                Ok(filled_in) if matches_any(HOST_PATHS, path) => {
+                    *text = absolute_host_path(path, &filled_in, &config_dir)
+                }
+                Ok(filled_in) => *text = filled_in,
+                Err(e) => problem(e, path),
+            }
+        }
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                fill_in_value(value, &resolve::join_path(path, key), variables, problem);
+            }
+        }
+        Value::Array(array) if resolve::is_array_of_tables(array) => {
+            for (index, entry) in array.iter_mut().enumerate() {
+                let entry_path = resolve::join_path(path, &index.to_string());
+                fill_in_value(entry, &entry_path, variables, problem);
+            }
+        }
+        Value::Array(array) => {
+            for entry in array.iter_mut() {
+                fill_in_value(entry, path, variables, problem);
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Variables<'a> {
+    vars: Table,
+    app_name: String,
+
+    /// Where each value came from, for `${CONFIG_DIR}`
+    origins: &'a BTreeMap<String, Origin>,
+    wineprefix_path: Option<&'a Path>,
+
+    /// The variables that are being filled in, to find the ones that use themselves
+    expanding: Vec<String>,
+}
+
+impl Variables<'_> {
+    /// Fill in the variables in `text`, a value from the config file in `config_dir`.
+    /// `deferred` text keeps the wineprefix variables for the launcher.
+    fn fill_in(&mut self, text: &str, config_dir: &Path, deferred: bool) -> Result<String> {
+        interpolate(text, deferred, |name| {
+            self.expand(name, config_dir, deferred)
+        })
+    }
+
+    fn expand(&mut self, name: &str, config_dir: &Path, deferred: bool) -> Result<Expansion> {
+        // Deferred text gets the wineprefix the app runs with instead
+        let wineprefix_path = if deferred { None } else { self.wineprefix_path };
+
+        let value = match name {
+            WINEPREFIX_VARIABLE | DRIVE_C_VARIABLE => match wineprefix_path {
+                None => return Ok(Expansion::Keep),
+                Some(wineprefix_path) if name == WINEPREFIX_VARIABLE => {
+                    host_path_string(wineprefix_path)
+                }
+                Some(wineprefix_path) => host_path_string(&wineprefix_path.join("drive_c")),
+            },
+            CONFIG_DIR_VARIABLE => host_path_string(config_dir),
+            APP_NAME_VARIABLE => {
+                let app_name = self.app_name.clone();
+                let app_name_dir = self.config_dir("app.name");
+                self.expand_variable(name, &app_name, &app_name_dir, deferred)?
+            }
+            _ => {
+                if let Some(variable) = name.strip_prefix(ENV_PREFIX) {
+                    env::var(variable).map_err(|_| {
+                        anyhow!(
+                            "The environment variable {} in ${{{}}} isn't set",
+                            variable,
+                            name
+                        )
+                    })?
+                } else {
+                    let var = match self.vars.get(name) {
+                        Some(Value::String(var)) => var.clone(),
+                        Some(_) => bail!("vars.{} has to be a string", name),
+                        None => bail!("{}", self.unknown_variable(name)),
+                    };
+                    let var_dir = self.config_dir(&format!("vars.{}", name));
+                    self.expand_variable(name, &var, &var_dir, deferred)?
+                }
+            }
+        };
+
+        Ok(Expansion::Value(value))
+    }
+
+    /// Fill in the variables in the variable `name`, unless that ends up using `name`
+    /// again
+    fn expand_variable(
+        &mut self,
+        name: &str,
+        value: &str,
+        config_dir: &Path,
+        deferred: bool,
+    ) -> Result<String> {
+        if self.expanding.iter().any(|expanding| expanding == name) {
+            let mut cycle = self.expanding.clone();
+            cycle.push(name.to_string());
+            bail!(
+                "${{{}}} uses itself: {}",
+                name,
+                cycle
+                    .iter()
+                    .skip_while(|expanding| *expanding != name)
+                    .map(|expanding| format!("${{{}}}", expanding))
+                    .collect::<Vec<_>>()
+                    .join(" uses ")
+            );
+        }
+
+        self.expanding.push(name.to_string());
+        let result = self.fill_in(value, config_dir, deferred);
+        self.expanding.pop();
+
+        result
+    }
+
+    fn unknown_variable(&self, name: &str) -> String {
+        let known = BUILT_IN_VARIABLES
+            .iter()
+            .copied()
+            .chain(self.vars.keys().map(String::as_str));
+
+        match validate::closest(name, known) {
+            Some(suggestion) => format!(
+                "Unknown variable ${{{}}}, did you mean ${{{}}}?",
+                name, suggestion
+            ),
+            None => format!(
+                "Unknown variable ${{{}}}, add it to [vars] or use ${{env:{}}} for an environment variable",
+                name, name
+            ),
+        }
+    }
+
+    /// The absolute directory of the config file the value at `path` came from
+    fn config_dir(&self, path: &str) -> PathBuf {
+        let file = resolve::origin(self.origins, path)
+            .map(|origin| origin.file.as_str())
+            .unwrap_or("");
+        let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())
+    }
+}
+
+/// Whether `path` is one of `patterns`, like `HOST_PATHS`
+fn matches_any(patterns: &[&str], path: &str) -> bool {
+    let components: Vec<&str> = path.split('.').collect();
+
+    patterns.iter().any(|host_path| {
+        let host_components: Vec<&str> = host_path.split('.').collect();
+        host_components.len() == components.len()
+            && host_components
+                .iter()
+                .zip(&components)
+                .all(|(host_component, component)| {
+                    host_component == component
+                        || (*host_component == "*" && component.parse::<usize>().is_ok())
+                })
+    })
+}
+
+/// `path` in the config, made absolute against the directory of its config file if it's
+/// a relative path on the host. A program without a `/` is only a path if it's in that
+/// directory, otherwise it's the name of a Windows program.
+fn absolute_host_path(key_path: &str, path: &str, config_dir: &Path) -> String {
+    let is_auto_icon = key_path == "app.icon" && path == "auto";
+    let is_program_name = matches_any(PROGRAM_PATHS, key_path)
+        && !path.contains('/')
+        && !config_dir.join(path).exists();
+    if path.is_empty()
+        || is_auto_icon
+        || is_program_name
+        || WindowsPath::is_windows_path(path)
+        || Path::new(path).is_absolute()
+    {
+        return path.to_string();
+    }
+
+    host_path_string(&config_dir.join(path))
+}
+
+fn host_path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Resolve and fill in `config`, written to a file in `dir`
+    fn fill_in_config(dir: &TempDir, config: &str) -> (Resolved, Vec<Diagnostic>) {
+        let path = dir.path().join("app.boxwine.toml");
+        fs::write(&path, config).unwrap();
+
+        let mut resolved = resolve::resolve(path.to_str().unwrap()).unwrap();
+        let diagnostics = fill_in(&mut resolved, Some(Path::new("/build/wineprefix")));
+        (resolved, diagnostics)
+    }
+
+    fn value<'a>(resolved: &'a Resolved, path: &str) -> &'a str {
+        let mut value = None;
+        for key in path.split('.') {
+            let table = value.map_or(&resolved.table, |value: &Value| value.as_table().unwrap());
+            value = Some(&table[key]);
+        }
+        value.unwrap().as_str().unwrap()
+    }
+
+    fn errors(resolved: &Resolved, diagnostics: &[Diagnostic]) -> String {
+        validate::report(&resolved.path, diagnostics).to_string()
+    }
+
+    #[test]
+    fn fills_in_variables_and_escapes() {
+        let dir = TempDir::new().unwrap();
+        let (resolved, diagnostics) = fill_in_config(
+            &dir,
+            r#"
+[vars]
+saves = "${DRIVE_C}/users/Public/${APP_NAME}"
+
+[app]
+name = "Game"
+display_name = "${APP_NAME} $${APP_NAME} costs $5"
+
+[app.entrypoint]
+program = "c:/game.exe"
+args = ["${saves}", "${WINEPREFIX}"]
+"#,
+        );
+
+        assert!(
+            diagnostics.is_empty(),
+            "{}",
+            errors(&resolved, &diagnostics)
+        );
+        assert_eq!(
+            value(&resolved, "app.display_name"),
+            "Game ${APP_NAME} costs $5"
+        );
+        // The entrypoint keeps the wineprefix for the launcher
+        let args = resolved.table["app"]["entrypoint"]["args"]
+            .as_array()
+            .unwrap();
+        assert_eq!(args[0].as_str(), Some("${DRIVE_C}/users/Public/Game"));
+        assert_eq!(args[1].as_str(), Some("${WINEPREFIX}"));
+    }
+
+    #[test]
+    fn reports_variables_that_use_themselves() {
+        let dir = TempDir::new().unwrap();
+        let (resolved, diagnostics) = fill_in_config(
+            &dir,
+            "[vars]\na = \"${b}\"\nb = \"x${a}\"\n\n[app]\nname = \"${a}\"\n",
+        );
+
+        // Each variable in the cycle is reported where it's defined, and the value that
+        // uses them isn't reported again
+        let errors = errors(&resolved, &diagnostics);
+        assert_eq!(diagnostics.len(), 2, "{}", errors);
+        assert!(
+            errors.contains(":2:1: ${a} uses itself: ${a} uses ${b} uses ${a}"),
+            "{}",
+            errors
+        );
+        assert!(
+            errors.contains(":3:1: ${b} uses itself: ${b} uses ${a} uses ${b}"),
+            "{}",
+            errors
+        );
+    }
+
+    #[test]
+    fn suggests_known_variables_for_unknown_ones() {
+        let dir = TempDir::new().unwrap();
+        let (resolved, diagnostics) =
+            fill_in_config(&dir, "[vars]\nsaves = \"x\"\n\n[app]\nname = \"${save}\"\n");
+
+        let errors = errors(&resolved, &diagnostics);
+        assert!(
+            errors.contains(":5:1: Unknown variable ${save}, did you mean ${saves}?"),
+            "{}",
+            errors
+        );
+    }
+
+    #[test]
+    fn resolves_relative_host_paths_against_the_config() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("setup"), "").unwrap();
+        let (resolved, diagnostics) = fill_in_config(
+            &dir,
+            r#"
+[app]
+icon = "icons/app.png"
+
+[[wine.run]]
+program = "installers/Setup.exe"
+
+[[wine.run]]
+program = "regedit"
+
+[[wine.run]]
+program = 'C:\windows\notepad.exe'
+
+[[wine.run]]
+program = "setup"
+"#,
+        );
+        assert!(
+            diagnostics.is_empty(),
+            "{}",
+            errors(&resolved, &diagnostics)
+        );
+
+        let config_dir = dir.path().canonicalize().unwrap();
+        let runs = resolved.table["wine"]["run"].as_array().unwrap();
+        let programs: Vec<&str> = runs
+            .iter()
+            .map(|run| run["program"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            value(&resolved, "app.icon"),
+            config_dir.join("icons/app.png").to_str().unwrap()
+        );
+        assert_eq!(
+            programs,
+            [
+                config_dir.join("installers/Setup.exe").to_str().unwrap(),
+                "regedit",
+                r"C:\windows\notepad.exe",
+                config_dir.join("setup").to_str().unwrap(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Characters windows doesn't allow in file names
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// An absolute Windows path like `C:\Program Files\App\app.exe`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowsPath {
+    /// The drive letter, in lowercase like wine's `dosdevices`
+    drive: char,
+    components: Vec<String>,
+}
+
+impl WindowsPath {
+    /// Parse a path that starts with a drive letter. Both `/` and `\` separate
+    /// components, and `.` and `..` are resolved.
+    pub fn parse(path: &str) -> Result<WindowsPath> {
+        let mut chars = path.chars();
+        let drive = match (chars.next(), chars.next()) {
+            (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => drive.to_ascii_lowercase(),
+            _ => bail!(
+                "{} isn't a Windows path, those start with a drive letter like c:/",
+                path
+            ),
+        };
+
+        let mut components: Vec<String> = Vec::new();
+        for component in chars.as_str().split(['/', '\\']) {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    if components.pop().is_none() {
+                        bail!("{} goes above the root of drive {}:", path, drive);
+                    }
+                }
+                _ => {
+                    if component.contains(RESERVED_CHARS) || component.contains(char::is_control) {
+                        bail!("{} has characters windows doesn't allow in it", path);
+                    }
+                    components.push(component.to_string());
+                }
+            }
+        }
+
+        Ok(WindowsPath { drive, components })
+    }
+
+    /// Whether `path` looks like a Windows path rather than one on the host
+    pub fn is_windows_path(path: &str) -> bool {
+        let mut chars = path.chars();
+        matches!(
+            (chars.next(), chars.next()),
+            (Some(drive), Some(':')) if drive.is_ascii_alphabetic()
+        )
+    }
+
+    pub fn drive(&self) -> char {
+        self.drive
+    }
+
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+
+    /// Whether this is the root of its drive
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Where this path is in the wineprefix at `wineprefix_path`. C: is the wineprefix's
+    /// `drive_c`, other drives are reached through their link in `dosdevices`.
+    ///
+    /// Windows doesn't care about case, so components that exist with different case
+    /// are found like windows would. Whatever doesn't exist yet is kept as it is.
+    pub fn host_path(&self, wineprefix_path: &Path) -> PathBuf {
+        let mut host_path = match self.drive {
+            'c' => wineprefix_path.join("drive_c"),
+            drive => wineprefix_path
+                .join("dosdevices")
+                .join(format!("{}:", drive)),
+        };
+
+        for component in &self.components {
+            let exact_path = host_path.join(component);
+            host_path = if fs::symlink_metadata(&exact_path).is_ok() {
+                exact_path
+            } else {
+                find_ignoring_case(&host_path, component).unwrap_or(exact_path)
+            };
+        }
+
+        host_path
+    }
+}
+
+/// The entry in `dir` named `name` in any case, if there is one
+fn find_ignoring_case(dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.to_lowercase();
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)
+        .map(|entry| entry.path())
+}
+
+/// The form wine expects, like `C:\Program Files\app.exe`
+impl fmt::Display for WindowsPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:\\{}",
+            self.drive.to_ascii_uppercase(),
+            self.components.join("\\")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn components(path: &WindowsPath) -> Vec<&str> {
+        path.components().iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn parses_drive_letters_and_both_slashes() {
+        let path = WindowsPath::parse(r"C:\Program Files/App\app.exe").unwrap();
+        assert_eq!(path.drive(), 'c');
+        assert_eq!(components(&path), ["Program Files", "App", "app.exe"]);
+
+        let path = WindowsPath::parse("d:/games/./old/../new/").unwrap();
+        assert_eq!(path.drive(), 'd');
+        assert_eq!(components(&path), ["games", "new"]);
+
+        assert!(WindowsPath::parse("c:").unwrap().is_root());
+        assert!(WindowsPath::parse("c:/games/..").unwrap().is_root());
+
+        assert!(WindowsPath::parse("/on/host").is_err());
+        assert!(WindowsPath::parse("1:/games").is_err());
+        assert!(WindowsPath::is_windows_path(r"Z:\"));
+        assert!(!WindowsPath::is_windows_path("relative/c:"));
+    }
+
+    #[test]
+    fn refuses_going_above_the_drive() {
+        assert!(WindowsPath::parse("c:/..").is_err());
+        assert!(WindowsPath::parse(r"c:\games\..\..\windows").is_err());
+    }
+
+    #[test]
+    fn refuses_reserved_characters() {
+        for path in [
+            "c:/what?.txt",
+            "c:/a*b",
+            "c:/a<b>",
+            "c:/a|b",
+            r#"c:/"quoted""#,
+            "c:/a:b",
+        ] {
+            assert!(WindowsPath::parse(path).is_err(), "{}", path);
+        }
+        assert!(WindowsPath::parse("c:/tab\there").is_err());
+    }
+
+    #[test]
+    fn finds_host_paths_ignoring_case() {
+        let dir = TempDir::new().unwrap();
+        let wineprefix = dir.path();
+        fs::create_dir_all(wineprefix.join("drive_c/Program Files/App")).unwrap();
+
+        let path = WindowsPath::parse("C:/program files/APP/New Dir/file.txt").unwrap();
+        assert_eq!(
+            path.host_path(wineprefix),
+            wineprefix.join("drive_c/Program Files/App/New Dir/file.txt")
+        );
+
+        let path = WindowsPath::parse("D:/data").unwrap();
+        assert_eq!(
+            path.host_path(wineprefix),
+            wineprefix.join("dosdevices/d:/data")
+        );
+    }
+
+    #[test]
+    fn displays_the_way_wine_expects() {
+        let path = WindowsPath::parse("c:/Program Files/App/app.exe").unwrap();
+        assert_eq!(path.to_string(), r"C:\Program Files\App\app.exe");
+        assert_eq!(WindowsPath::parse(&path.to_string()).unwrap(), path);
+        assert_eq!(WindowsPath::parse("c:/").unwrap().to_string(), r"C:\");
+    }
+}